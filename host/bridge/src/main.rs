@@ -0,0 +1,329 @@
+//! Bridge between the serial port of the heat control and a MQTT broker
+//!
+//! - MQTT frames are published to `<base>/<topic>`
+//! - JSON snapshots are published to `<base>/Snapshot`
+//! - Home Assistant discovery frames are published retained to
+//!   `<discovery prefix>/<component>/<base>/<object id>/config`
+//! - Replies to commands are published to `<base>/Reply`
+//! - Messages on `<base>/Command` are send as command line to the controller
+//! - `<base>/status` is `online` while the bridge is connected, `offline` otherwise
+//!
+//! With `--binary` the binary telemetry of the firmware feature `binary` is decoded and published
+//! to the same topics. Home Assistant discovery is only send by the controller in text mode.
+//!
+//! Without hardware the bridge can be tested with a pseudo terminal and a local broker:
+//!
+//! ```text
+//! socat -d -d pty,raw,echo=0,link=/tmp/heat_control pty,raw,echo=0,link=/tmp/controller
+//! mosquitto -v
+//! heat_control_bridge --serial /tmp/heat_control
+//! ```
+//!
+//! Frames written to `/tmp/controller` are published, commands show up there.
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use heat_control_protocol::binary::{self, Decoder, Message};
+use heat_control_protocol::{is_restart, parse_line, Frame, Kind, Line, SequenceTracker};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+
+/// Base topic used in the discovery configs of the controller
+const CONTROLLER_BASE_TOPIC: &str = "heat_control";
+
+#[derive(Parser)]
+#[command(about = "Publish the telemetry of the heat control to a MQTT broker")]
+struct Args {
+    /// Serial port of the controller
+    #[arg(long, default_value = "/dev/ttyUSB0")]
+    serial: String,
+    /// Baud rate of the serial port
+    #[arg(long, default_value_t = 9600)]
+    baud: u32,
+    /// Host name of the MQTT broker
+    #[arg(long, default_value = "localhost")]
+    host: String,
+    /// Port of the MQTT broker
+    #[arg(long, default_value_t = 1883)]
+    port: u16,
+    /// User name for the broker
+    #[arg(long)]
+    user: Option<String>,
+    /// Password for the broker
+    #[arg(long, requires = "user")]
+    password: Option<String>,
+    /// Client id used at the broker
+    #[arg(long, default_value = "heat_control_bridge")]
+    client_id: String,
+    /// Topic all values are published below
+    #[arg(long, default_value = CONTROLLER_BASE_TOPIC)]
+    base_topic: String,
+    /// Prefix of the Home Assistant discovery topics
+    #[arg(long, default_value = "homeassistant")]
+    discovery_prefix: String,
+    /// Quality of service of the published values
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+    qos: u8,
+    /// Publish the values retained
+    #[arg(long)]
+    retain: bool,
+    /// Decode the binary telemetry instead of text lines
+    #[arg(long)]
+    binary: bool,
+}
+
+fn qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let serial = serialport::new(&args.serial, args.baud)
+        .timeout(Duration::from_secs(1))
+        .open()?;
+    let command_serial = Arc::new(Mutex::new(serial.try_clone()?));
+
+    let status_topic = format!("{}/status", args.base_topic);
+    let command_topic = format!("{}/Command", args.base_topic);
+
+    let mut options = MqttOptions::new(&args.client_id, &args.host, args.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        &status_topic,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let (Some(user), Some(password)) = (&args.user, &args.password) {
+        options.set_credentials(user, password);
+    }
+    let (client, mut connection) = Client::new(options, 64);
+
+    // Subscribe and announce the bridge on every (re)connect. Forward commands to the controller
+    {
+        let client = client.clone();
+        let command_topic = command_topic.clone();
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        eprintln!("Connected to broker");
+                        client
+                            .subscribe(&command_topic, QoS::AtLeastOnce)
+                            .and_then(|_| {
+                                client.publish(&status_topic, QoS::AtLeastOnce, true, "online")
+                            })
+                            .unwrap_or_else(|error| eprintln!("MQTT: {}", error));
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish)))
+                        if publish.topic == command_topic =>
+                    {
+                        let command = String::from_utf8_lossy(&publish.payload);
+                        let mut serial = command_serial.lock().unwrap();
+                        if let Err(error) = writeln!(serial, "{}", command.trim()) {
+                            eprintln!("Serial: {}", error);
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(error) => {
+                        eprintln!("MQTT: {}", error);
+                        thread::sleep(Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+    }
+
+    let mut bridge = Bridge {
+        client,
+        args,
+        sequence: SequenceTracker::new(),
+        lost: 0,
+    };
+    bridge.run(serial)
+}
+
+struct Bridge {
+    client: Client,
+    args: Args,
+    sequence: SequenceTracker,
+    /// Frames that were lost or corrupted
+    lost: u64,
+}
+
+impl Bridge {
+    /// Read lines from the serial port and handle them
+    fn run(
+        &mut self,
+        mut serial: Box<dyn serialport::SerialPort>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut received = Vec::new();
+        let mut decoder = Decoder::new();
+        let mut buffer = [0_u8; 256];
+        loop {
+            let len = match serial.read(&mut buffer) {
+                Ok(len) => len,
+                Err(error) if error.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            if self.args.binary {
+                for byte in &buffer[..len] {
+                    match decoder.push(*byte) {
+                        Some(Ok(packet)) => self.handle_packet(packet),
+                        Some(Err(error)) => {
+                            self.lost += 1;
+                            eprintln!("Dropped message: {}", error);
+                        }
+                        None => (),
+                    }
+                }
+                continue;
+            }
+
+            received.extend_from_slice(&buffer[..len]);
+
+            while let Some(end) = received.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = received.drain(..=end).collect();
+                self.handle_line(&String::from_utf8_lossy(&line));
+            }
+        }
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        match parse_line(line) {
+            Ok(Line::Frame(frame)) => {
+                self.check_sequence(frame.sequence, frame.is_restart());
+                self.handle_frame(&frame);
+            }
+            Ok(Line::Plain(text)) if text.is_empty() => (),
+            Ok(Line::Plain(text)) => eprintln!("Controller: {}", text),
+            Err(error) => {
+                self.lost += 1;
+                eprintln!("Dropped line: {} ({})", error, line.trim_end());
+            }
+        }
+    }
+
+    /// Count the frames lost before a received one. `restart` is set for the messages the
+    /// controller sends after a reset, it starts counting at 0 again
+    fn check_sequence(&mut self, sequence: u16, restart: bool) {
+        if restart {
+            self.sequence.reset();
+        }
+        let lost = self.sequence.update(sequence);
+        if lost > 0 {
+            self.lost += lost as u64;
+            eprintln!("Lost {} frames ({} total)", lost, self.lost);
+        }
+    }
+
+    /// Publish a binary message to the topics of the text protocol
+    fn handle_packet(&mut self, packet: binary::Packet) {
+        let restart = match &packet.message {
+            Message::Text(kind, payload) => is_restart(*kind, payload),
+            _ => false,
+        };
+        self.check_sequence(packet.sequence, restart);
+
+        let base = &self.args.base_topic;
+        let on_off = |value: bool| if value { "On" } else { "Off" }.to_string();
+        match packet.message {
+            Message::Temperatures(temperatures) => {
+                for (topic, temperature) in [
+                    ("Puffer_Oben", temperatures.buffer_top),
+                    ("Puffer_Unten", temperatures.buffer_bottom),
+                    ("Warmwasser", temperatures.warm_water),
+                    ("Kessel", temperatures.boiler),
+                ] {
+                    if let Some(temperature) = temperature {
+                        let topic = format!("{}/Temperature/{}", base, topic);
+                        self.publish(topic, temperature.to_string(), None);
+                    }
+                }
+            }
+            Message::Io(inputs, outputs) => {
+                for (topic, value) in [
+                    ("Inputs/BrennerStart", inputs.start_burner),
+                    ("Inputs/Pumpe_Warmwasser", inputs.warm_water_pump),
+                    ("Inputs/Pumpe_Heizung", inputs.heating_pump),
+                    ("Outputs/Brenner_Sperre", outputs.burner_inhibit),
+                    ("Outputs/Magnetventil_Puffer", outputs.magnet_valve_buffer),
+                    ("Outputs/Pumpe_Puffer", outputs.pump_buffer),
+                ] {
+                    self.publish(format!("{}/{}", base, topic), on_off(value), None);
+                }
+            }
+            Message::State(state) => {
+                let name = binary::state_name(state)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| state.to_string());
+                self.publish(format!("{}/State", base), name, None);
+            }
+            Message::Text(kind, payload) => self.handle_frame(&Frame {
+                sequence: packet.sequence,
+                kind,
+                payload,
+            }),
+        }
+    }
+
+    fn handle_frame(&self, frame: &Frame) {
+        let base = &self.args.base_topic;
+        match frame.kind {
+            Kind::Debug => eprintln!("Debug: {}", frame.payload),
+            Kind::Info => eprintln!("Info: {}", frame.payload),
+            Kind::Warn => eprintln!("Warning: {}", frame.payload),
+            Kind::Error => eprintln!("Error: {}", frame.payload),
+            Kind::Mqtt => {
+                if let Some((topic, value)) = frame.topic_value() {
+                    self.publish(format!("{}/{}", base, topic), value.to_string(), None);
+                }
+            }
+            Kind::Json => self.publish(format!("{}/Snapshot", base), frame.payload.clone(), None),
+            Kind::Announce => {
+                if let Some((object, config)) = frame.topic_value() {
+                    let (component, object_id) =
+                        object.split_once('/').unwrap_or(("sensor", object));
+                    let topic = format!(
+                        "{}/{}/{}/{}/config",
+                        self.args.discovery_prefix, component, base, object_id
+                    );
+                    // Point the configs to the base topic of the bridge
+                    let config = config.replace(
+                        &format!("\"~\":\"{}\"", CONTROLLER_BASE_TOPIC),
+                        &format!("\"~\":\"{}\"", base),
+                    );
+                    self.publish(topic, config, Some(true));
+                }
+            }
+            Kind::Reply => {
+                eprintln!("Reply: {}", frame.payload);
+                self.publish(
+                    format!("{}/Reply", base),
+                    frame.payload.clone(),
+                    Some(false),
+                );
+            }
+        }
+    }
+
+    /// Publish a message. Uses the configured retain flag if `retain` is not given
+    fn publish(&self, topic: String, payload: String, retain: Option<bool>) {
+        let retain = retain.unwrap_or(self.args.retain);
+        if let Err(error) = self
+            .client
+            .try_publish(topic, qos(self.args.qos), retain, payload)
+        {
+            eprintln!("MQTT: {}", error);
+        }
+    }
+}
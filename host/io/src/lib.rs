@@ -0,0 +1,71 @@
+//! Inputs and outputs of the firmware compiled for the host
+//!
+//! The pins of the hardware abstraction layer are replaced by simulated pins, so switch
+//! protection and debouncing run with the firmware code.
+
+#[path = "../../../src/time.rs"]
+mod time;
+
+mod timer {
+    pub use crate::time::{Duration, Instant};
+}
+
+#[path = "../../../src/io.rs"]
+mod io;
+
+pub use io::*;
+pub use time::{Duration, Instant};
+
+/// Simulated pins with the interface of the hardware abstraction layer used by the firmware
+pub mod hal {
+    pub mod port {
+        use std::cell::Cell;
+        use std::marker::PhantomData;
+        use std::rc::Rc;
+
+        pub mod mode {
+            pub struct Output;
+            pub struct Input<MODE>(MODE);
+            pub struct Floating;
+        }
+
+        /// Pin with a level that is shared by all clones, so a test can drive an input or read
+        /// an output that was moved into the firmware structs
+        pub struct Pin<MODE> {
+            level: Rc<Cell<bool>>,
+            mode: PhantomData<MODE>,
+        }
+
+        impl<MODE> Default for Pin<MODE> {
+            fn default() -> Self {
+                Self {
+                    level: Rc::new(Cell::new(false)),
+                    mode: PhantomData,
+                }
+            }
+        }
+
+        impl<MODE> Clone for Pin<MODE> {
+            fn clone(&self) -> Self {
+                Self {
+                    level: self.level.clone(),
+                    mode: PhantomData,
+                }
+            }
+        }
+
+        impl<MODE> Pin<MODE> {
+            pub fn set_high(&mut self) {
+                self.level.set(true);
+            }
+
+            pub fn set_low(&mut self) {
+                self.level.set(false);
+            }
+
+            pub fn is_high(&self) -> bool {
+                self.level.get()
+            }
+        }
+    }
+}
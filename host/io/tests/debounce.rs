@@ -0,0 +1,102 @@
+//! Debouncing of the inputs sampled in the control cycle of one second
+
+use heat_control_io::hal::port::{mode, Pin};
+use heat_control_io::{Duration, EdgeKind, InputId, Inputs, Instant};
+
+type InputPin = Pin<mode::Input<mode::Floating>>;
+
+const CYCLE: Duration = Duration::from_secs(1);
+
+struct Simulation {
+    start_burner: InputPin,
+    heating_pump: InputPin,
+    inputs: Inputs,
+    time: Instant,
+}
+
+impl Simulation {
+    /// Inputs with the given levels at startup
+    fn new(start_burner_high: bool) -> Self {
+        let mut start_burner = InputPin::default();
+        if start_burner_high {
+            start_burner.set_high();
+        }
+        let heating_pump = InputPin::default();
+        let inputs = Inputs::new(
+            start_burner.clone(),
+            InputPin::default(),
+            heating_pump.clone(),
+        );
+        Self {
+            start_burner,
+            heating_pump,
+            inputs,
+            time: Instant::from_millis(0),
+        }
+    }
+
+    /// Run a control cycle and return the edges of the input
+    fn cycle(&mut self, input: InputId) -> Vec<EdgeKind> {
+        self.time = self.time + CYCLE;
+        self.inputs
+            .get_inputs(self.time)
+            .iter()
+            .flatten()
+            .filter(|edge| edge.input == input)
+            .map(|edge| edge.kind)
+            .collect()
+    }
+}
+
+#[test]
+fn samples() {
+    let mut simulation = Simulation::new(false);
+
+    // A single sample is a bounce
+    simulation.start_burner.set_high();
+    assert!(simulation.cycle(InputId::StartBurner).is_empty());
+    simulation.start_burner.set_low();
+    assert!(simulation.cycle(InputId::StartBurner).is_empty());
+    assert!(!simulation.inputs.get_start_burner());
+
+    simulation.start_burner.set_high();
+    assert!(simulation.cycle(InputId::StartBurner).is_empty());
+    assert!(simulation.cycle(InputId::StartBurner) == [EdgeKind::Rising]);
+    assert!(simulation.inputs.get_start_burner());
+}
+
+#[test]
+fn time() {
+    let mut simulation = Simulation::new(false);
+
+    // Shorter than the debounce time of 5 s
+    simulation.heating_pump.set_high();
+    for _ in 0..4 {
+        assert!(simulation.cycle(InputId::HeatingPump).is_empty());
+    }
+    simulation.heating_pump.set_low();
+    assert!(simulation.cycle(InputId::HeatingPump).is_empty());
+    assert!(!simulation.inputs.get_heating_pump());
+
+    simulation.heating_pump.set_high();
+    for _ in 0..5 {
+        assert!(simulation.cycle(InputId::HeatingPump).is_empty());
+    }
+    assert!(simulation.cycle(InputId::HeatingPump) == [EdgeKind::Rising]);
+    assert!(simulation.inputs.get_heating_pump());
+
+    simulation.heating_pump.set_low();
+    for _ in 0..5 {
+        assert!(simulation.cycle(InputId::HeatingPump).is_empty());
+    }
+    assert!(simulation.cycle(InputId::HeatingPump) == [EdgeKind::Falling]);
+}
+
+#[test]
+fn active_at_startup_is_no_edge() {
+    let mut simulation = Simulation::new(true);
+    assert!(simulation.inputs.get_start_burner());
+    for _ in 0..3 {
+        assert!(simulation.cycle(InputId::StartBurner).is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+//! Burner start of the simulation, which runs through the debouncing like the pin. Run with
+//! `cargo test --features simulation --test simulation`
+#![cfg(feature = "simulation")]
+
+use heat_control_io::hal::port::Pin;
+use heat_control_io::{Duration, Edge, EdgeKind, InputId, Inputs, Instant};
+
+const CYCLE: Duration = Duration::from_secs(1);
+
+fn start_burner_edges(edges: [Option<Edge>; 3]) -> Vec<EdgeKind> {
+    edges
+        .iter()
+        .flatten()
+        .filter(|edge| edge.input == InputId::StartBurner)
+        .map(|edge| edge.kind)
+        .collect()
+}
+
+#[test]
+fn simulated_start_is_debounced() {
+    let mut inputs = Inputs::new(Pin::default(), Pin::default(), Pin::default());
+    let mut time = Instant::from_millis(0);
+
+    // A single sample is a bounce
+    inputs.simulate_start_burner(true);
+    assert!(start_burner_edges(inputs.get_inputs(time)).is_empty());
+    inputs.simulate_start_burner(false);
+    time = time + CYCLE;
+    assert!(start_burner_edges(inputs.get_inputs(time)).is_empty());
+    assert!(!inputs.get_start_burner());
+
+    // The edge follows once and only once
+    inputs.simulate_start_burner(true);
+    let mut edges = Vec::new();
+    for _ in 0..5 {
+        time = time + CYCLE;
+        edges.extend(start_burner_edges(inputs.get_inputs(time)));
+    }
+    assert!(edges == [EdgeKind::Rising]);
+    assert!(inputs.get_start_burner());
+}
@@ -0,0 +1,101 @@
+//! Switch protection of the outputs with the control cycle of one second. The buffer cycle of
+//! the state machine is tested in `heat_control_statemachine`
+
+use heat_control_io::hal::port::Pin;
+use heat_control_io::{Duration, Instant, OutputId, Outputs};
+
+const CYCLE: Duration = Duration::from_secs(1);
+
+fn outputs() -> Outputs {
+    Outputs::new(Pin::default(), Pin::default(), Pin::default())
+}
+
+fn not_delayed(delayed: [Option<OutputId>; 3]) -> bool {
+    delayed.iter().all(Option::is_none)
+}
+
+#[test]
+fn safe_state_is_never_delayed() {
+    let mut outputs = outputs();
+    let mut time = Instant::from_millis(0);
+    outputs.set_burner_inhibit(true);
+    outputs.set_pump_buffer(true);
+    outputs.set_outputs(time);
+    assert!(outputs.get_burner_inhibit());
+    assert!(outputs.get_pump_buffer());
+
+    // Right after switching on, within any minimum on time
+    time = time + CYCLE;
+    outputs.set_burner_inhibit(false);
+    outputs.set_pump_buffer(false);
+    assert!(not_delayed(outputs.set_outputs(time)));
+    assert!(!outputs.get_burner_inhibit());
+    assert!(!outputs.get_pump_buffer());
+}
+
+#[test]
+fn pump_start_is_delayed() {
+    let mut outputs = outputs();
+    let mut time = Instant::from_millis(0);
+    outputs.set_pump_buffer(true);
+    outputs.set_outputs(time);
+    time = time + CYCLE;
+    outputs.set_pump_buffer(false);
+    outputs.set_outputs(time);
+
+    // Minimum off time
+    time = time + CYCLE;
+    outputs.set_pump_buffer(true);
+    assert!(outputs.set_outputs(time) == [None, None, Some(OutputId::PumpBuffer)]);
+    assert!(!outputs.get_pump_buffer());
+    time = time + Duration::from_secs(30);
+    assert!(not_delayed(outputs.set_outputs(time)));
+    assert!(outputs.get_pump_buffer());
+    assert_eq!(outputs.get_delayed_count(OutputId::PumpBuffer), 1);
+
+    // Starts per window
+    let mut delayed = false;
+    for _ in 0..100 {
+        time = time + Duration::from_secs(30);
+        outputs.set_pump_buffer(!outputs.get_pump_buffer());
+        delayed |= outputs.set_outputs(time)[2].is_some();
+    }
+    assert!(delayed);
+    assert!(outputs.get_switch_count(OutputId::PumpBuffer) < 2 * 40 + 2);
+}
+
+/// Request a start of the pump and stop it again after ten seconds. Returns true if it started
+fn pump_start(outputs: &mut Outputs, time: Instant) -> bool {
+    outputs.set_pump_buffer(true);
+    outputs.set_outputs(time);
+    let started = outputs.get_pump_buffer();
+    outputs.set_pump_buffer(false);
+    outputs.set_outputs(time + Duration::from_secs(10));
+    started
+}
+
+#[test]
+fn starts_are_counted_per_clock_window() {
+    let mut outputs = outputs();
+    let minute = Duration::from_secs(60);
+
+    // All starts of the first window in its last 40 minutes
+    let mut time = Instant::from_millis(0) + Duration::from_secs(19 * 60 + 30);
+    for _ in 0..40 {
+        assert!(pump_start(&mut outputs, time), "at {:?}", time);
+        time = time + minute;
+    }
+    assert!(!pump_start(&mut outputs, time), "at {:?}", time);
+
+    // The next window begins one hour after startup with all starts available
+    time = Instant::from_millis(0) + Duration::from_secs(60 * 60);
+    for _ in 0..40 {
+        assert!(pump_start(&mut outputs, time), "at {:?}", time);
+        time = time + minute;
+    }
+    assert!(!pump_start(&mut outputs, time), "at {:?}", time);
+
+    // Each of the 80 starts is followed by a stop. The hour from 30 to 90 minutes after startup
+    // holds 60 of them
+    assert_eq!(outputs.get_switch_count(OutputId::PumpBuffer), 4 * 40);
+}
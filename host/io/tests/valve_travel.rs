@@ -0,0 +1,122 @@
+//! Travel time of the valve with the control cycle of one second. The state machine starts the
+//! pump when the travel reports the valve open
+
+use heat_control_io::hal::port::Pin;
+use heat_control_io::{
+    Duration, Instant, OutputId, Outputs, Override, Travel, ValveTravel, VALVE_OPEN_TIMEOUT,
+    VALVE_TRAVEL_TIME,
+};
+
+const CYCLE: Duration = Duration::from_secs(1);
+
+fn outputs() -> Outputs {
+    Outputs::new(Pin::default(), Pin::default(), Pin::default())
+}
+
+/// Request the valve open every cycle like `ValveOpening` until the travel is finished. Returns
+/// the time the valve output switched on and the time the pump would start
+fn open_valve(outputs: &mut Outputs, time: &mut Instant) -> (Instant, Instant) {
+    let mut travel = ValveTravel::default();
+    let mut switched_on = None;
+    for _ in 0..120 {
+        outputs.set_magnet_valve_buffer(true);
+        if travel.update(outputs.get_magnet_valve_buffer(), *time) == Travel::Open {
+            return (switched_on.unwrap(), *time);
+        }
+        outputs.set_outputs(*time);
+        if outputs.get_magnet_valve_buffer() && switched_on.is_none() {
+            switched_on = Some(*time);
+        }
+        *time = *time + CYCLE;
+    }
+    panic!("valve not open at {:?}", time);
+}
+
+#[test]
+fn travel_starts_when_the_valve_switches() {
+    let mut outputs = outputs();
+    let mut time = Instant::from_millis(0);
+
+    let (switched_on, pump_start) = open_valve(&mut outputs, &mut time);
+    assert_eq!(switched_on, Instant::from_millis(0));
+    // The switch is seen by the state machine one cycle later
+    assert!(pump_start - switched_on > VALVE_TRAVEL_TIME);
+    assert!(pump_start - switched_on <= VALVE_TRAVEL_TIME + CYCLE + CYCLE);
+}
+
+#[test]
+fn travel_waits_for_a_delayed_valve() {
+    let mut outputs = outputs();
+    let mut time = Instant::from_millis(0);
+
+    // Open and close the valve, so the minimum off time delays the next opening
+    outputs.set_magnet_valve_buffer(true);
+    outputs.set_outputs(time);
+    time = time + Duration::from_secs(10);
+    outputs.set_magnet_valve_buffer(false);
+    outputs.set_outputs(time);
+    assert!(!outputs.get_magnet_valve_buffer());
+    time = time + CYCLE;
+
+    let requested = time;
+    let (switched_on, pump_start) = open_valve(&mut outputs, &mut time);
+    assert!(switched_on - requested >= Duration::from_secs(8));
+    assert!(pump_start - switched_on > VALVE_TRAVEL_TIME);
+}
+
+#[test]
+fn travel_restarts_when_the_valve_closes() {
+    let mut travel = ValveTravel::default();
+    let mut time = Instant::from_millis(0);
+
+    assert_eq!(travel.update(true, time), Travel::Moving);
+    time = time + Duration::from_secs(20);
+    assert_eq!(travel.update(false, time), Travel::Moving);
+    time = time + CYCLE;
+    assert_eq!(travel.update(true, time), Travel::Moving);
+    time = time + Duration::from_secs(20);
+    assert_eq!(travel.update(true, time), Travel::Moving);
+    time = time + Duration::from_secs(11);
+    assert_eq!(travel.update(true, time), Travel::Open);
+}
+
+#[test]
+fn travel_times_out_if_the_valve_is_held_off() {
+    let mut outputs = outputs();
+    let mut travel = ValveTravel::default();
+    let start = Instant::from_millis(0);
+    let mut time = start;
+    outputs.set_override(OutputId::MagnetValveBuffer, Override::ForceOff, time, None);
+
+    loop {
+        outputs.set_magnet_valve_buffer(true);
+        match travel.update(outputs.get_magnet_valve_buffer(), time) {
+            Travel::Moving => {}
+            Travel::Open => panic!("valve open at {:?}", time),
+            Travel::Timeout => break,
+        }
+        outputs.set_outputs(time);
+        time = time + CYCLE;
+    }
+    assert!(time - start > VALVE_OPEN_TIMEOUT);
+    assert!(time - start <= VALVE_OPEN_TIMEOUT + CYCLE);
+}
+
+#[test]
+fn open_valve_does_not_time_out() {
+    let mut travel = ValveTravel::default();
+    let start = Instant::from_millis(0);
+
+    // The valve switched on shortly before the timeout and is still moving after it
+    let time = start + VALVE_OPEN_TIMEOUT - Duration::from_secs(5);
+    assert_eq!(travel.update(false, start), Travel::Moving);
+    assert_eq!(travel.update(true, time), Travel::Moving);
+    assert_eq!(
+        travel.update(true, time + Duration::from_secs(10)),
+        Travel::Moving
+    );
+    assert_eq!(
+        travel.update(true, time + VALVE_TRAVEL_TIME + CYCLE),
+        Travel::Open
+    );
+}
@@ -0,0 +1,24 @@
+//! Modbus RTU slave of the firmware, to test it with a simulated serial stream
+//!
+//! The register map runs on the inputs and outputs of `heat_control_io`.
+
+#[path = "../../../src/modbus.rs"]
+mod modbus;
+
+#[path = "../../../src/modbus_port.rs"]
+mod modbus_port;
+
+#[path = "../../../src/parameters.rs"]
+mod parameters;
+
+mod io {
+    pub use heat_control_io::*;
+}
+
+mod timer {
+    pub use heat_control_io::{Duration, Instant};
+}
+
+pub use modbus::*;
+pub use modbus_port::*;
+pub use parameters::*;
@@ -0,0 +1,460 @@
+//! Requests fed byte by byte into the slave as they arrive on the serial port at 9600 baud
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use heat_control_io::hal::port::Pin;
+use heat_control_io::{Duration, Inputs, Instant, OutputId, Outputs, Override};
+use heat_control_modbus::{
+    crc, is_frame_gap, ModbusPort, Parameters, Receive, RegisterMap, Transmit, SLAVE_ADDRESS,
+};
+
+const ADDRESS: u8 = SLAVE_ADDRESS;
+
+/// Serial line with a time resolution of 1 ms, about the time of one character. The bytes are
+/// queued and marked after a frame gap like by the receive interrupt
+#[derive(Default)]
+struct LineState {
+    received: VecDeque<(u8, bool)>,
+    time: u32,
+    last_received: u32,
+}
+
+/// Receive side of the line, owned by the port
+#[derive(Clone, Default)]
+struct Line(Rc<RefCell<LineState>>);
+
+impl Receive for Line {
+    fn read_frame_byte(&mut self) -> Option<(u8, bool)> {
+        self.0.borrow_mut().received.pop_front()
+    }
+
+    fn silence(&self) -> Duration {
+        let line = self.0.borrow();
+        Duration::from_millis(line.time - line.last_received)
+    }
+
+    fn overflows(&self) -> u16 {
+        0
+    }
+}
+
+#[derive(Default)]
+struct Responses(Vec<Vec<u8>>);
+
+impl Transmit for Responses {
+    fn write_raw(&mut self, data: &[u8]) {
+        self.0.push(data.to_vec());
+    }
+}
+
+/// Controller with the port of the firmware. The main loop polls the port every ms
+struct Simulation {
+    line: Line,
+    port: ModbusPort<Line>,
+    temperatures: [Option<i16>; 4],
+    inputs: Inputs,
+    outputs: Outputs,
+    parameters: Parameters,
+    state: u8,
+    /// Cleared while a task is running
+    polling: bool,
+    responses: Responses,
+}
+
+impl Simulation {
+    fn new() -> Self {
+        Self::with_inputs([false; 3])
+    }
+
+    /// Start burner, warm water pump and heating pump
+    fn with_inputs(levels: [bool; 3]) -> Self {
+        let pins: [Pin<_>; 3] = Default::default();
+        for (pin, level) in pins.iter().zip(levels) {
+            if level {
+                pin.clone().set_high();
+            }
+        }
+        let [start_burner, warm_water_pump, heating_pump] = pins;
+
+        let line = Line::default();
+        Self {
+            port: ModbusPort::new(line.clone()),
+            line,
+            temperatures: [None; 4],
+            inputs: Inputs::new(start_burner, warm_water_pump, heating_pump),
+            outputs: Outputs::new(Pin::default(), Pin::default(), Pin::default()),
+            parameters: Parameters::default(),
+            state: 0,
+            polling: true,
+            responses: Responses::default(),
+        }
+    }
+
+    fn time(&self) -> u32 {
+        self.line.0.borrow().time
+    }
+
+    /// Receive bytes without a pause between them
+    fn receive(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            {
+                let mut line = self.line.0.borrow_mut();
+                let frame_start = is_frame_gap(line.time - line.last_received);
+                line.received.push_back((*byte, frame_start));
+                line.last_received = line.time;
+            }
+            self.wait(1);
+        }
+    }
+
+    /// Keep the line silent
+    fn wait(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.line.0.borrow_mut().time += 1;
+            if self.polling {
+                self.poll();
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        let time = Instant::from_millis(self.time());
+        let mut registers = RegisterMap {
+            temperatures: self.temperatures,
+            inputs: &self.inputs,
+            outputs: &mut self.outputs,
+            parameters: &mut self.parameters,
+            state: self.state,
+            time,
+        };
+        self.port.poll(&mut registers, &mut self.responses);
+    }
+
+    /// Send a request and return the responses
+    fn request(&mut self, content: &[u8]) -> Vec<Vec<u8>> {
+        self.receive(&with_crc(content));
+        self.wait(10);
+        self.take_responses()
+    }
+
+    fn take_responses(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.responses.0)
+    }
+
+    /// Loading start and stop difference
+    fn loading(&self) -> (i16, i16) {
+        (
+            self.parameters.loading_start_difference,
+            self.parameters.loading_stop_difference,
+        )
+    }
+}
+
+fn with_crc(content: &[u8]) -> Vec<u8> {
+    let mut frame = content.to_vec();
+    frame.extend_from_slice(&crc(content).to_le_bytes());
+    frame
+}
+
+#[test]
+fn crc_matches_reference() {
+    // Read two holding registers from the example of the specification
+    assert_eq!(
+        with_crc(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]),
+        [0x01, 0x03, 0x00, 0x00, 0x00, 0x02, 0xC4, 0x0B]
+    );
+}
+
+#[test]
+fn read_temperatures() {
+    let mut simulation = Simulation::new();
+    simulation.temperatures = [Some(553), Some(-12), None, Some(0)];
+
+    assert_eq!(
+        simulation.request(&[ADDRESS, 4, 0, 1, 0, 3]),
+        [with_crc(&[
+            ADDRESS, 4, 6, 0xFF, 0xF4, 0x80, 0x00, 0x00, 0x00
+        ])]
+    );
+}
+
+#[test]
+fn read_inputs_and_outputs() {
+    let mut simulation = Simulation::with_inputs([false, true, true]);
+    simulation.outputs.set_burner_inhibit(true);
+    simulation.outputs.set_pump_buffer(true);
+    simulation.outputs.set_outputs(Instant::from_millis(0));
+
+    assert_eq!(
+        simulation.request(&[ADDRESS, 1, 0, 0, 0, 3]),
+        [with_crc(&[ADDRESS, 1, 1, 0b101])]
+    );
+    assert_eq!(
+        simulation.request(&[ADDRESS, 2, 0, 0, 0, 3]),
+        [with_crc(&[ADDRESS, 2, 1, 0b110])]
+    );
+}
+
+#[test]
+fn read_state_parameters_and_overrides() {
+    let mut simulation = Simulation::new();
+    simulation.state = 6;
+    simulation.outputs.set_override(
+        OutputId::PumpBuffer,
+        Override::ForceOff,
+        Instant::from_millis(0),
+        None,
+    );
+
+    let mut expected = vec![ADDRESS, 3, 28];
+    for value in [6, 550, 50, 600, 80, 30, 50, 30, 5, 600, 30, 0, 0, 2] {
+        expected.extend_from_slice(&(value as u16).to_be_bytes());
+    }
+    assert_eq!(
+        simulation.request(&[ADDRESS, 3, 0, 0, 0, 14]),
+        [with_crc(&expected)]
+    );
+}
+
+#[test]
+fn write_coils_overrides_the_outputs() {
+    let mut simulation = Simulation::new();
+
+    let request = [ADDRESS, 5, 0, 2, 0xFF, 0x00];
+    assert_eq!(simulation.request(&request), [with_crc(&request)]);
+    assert!(simulation.outputs.get_override(OutputId::PumpBuffer) == Override::ForceOn);
+
+    assert_eq!(
+        simulation.request(&[ADDRESS, 15, 0, 0, 0, 2, 1, 0b01]),
+        [with_crc(&[ADDRESS, 15, 0, 0, 0, 2])]
+    );
+    assert!(simulation.outputs.get_override(OutputId::BurnerInhibit) == Override::ForceOn);
+    assert!(simulation.outputs.get_override(OutputId::MagnetValveBuffer) == Override::ForceOff);
+
+    // Coils show the physical outputs
+    simulation.outputs.set_outputs(Instant::from_millis(0));
+    assert_eq!(
+        simulation.request(&[ADDRESS, 1, 0, 0, 0, 3]),
+        [with_crc(&[ADDRESS, 1, 1, 0b101])]
+    );
+}
+
+#[test]
+fn write_single_registers() {
+    let mut simulation = Simulation::new();
+
+    let request = [ADDRESS, 6, 0, 1, 0x02, 0x58];
+    assert_eq!(simulation.request(&request), [with_crc(&request)]);
+    assert_eq!(simulation.parameters.min_buffer_temperature, 600);
+
+    let request = [ADDRESS, 6, 0, 12, 0, 1];
+    assert_eq!(simulation.request(&request), [with_crc(&request)]);
+    assert!(simulation.outputs.get_override(OutputId::MagnetValveBuffer) == Override::ForceOn);
+
+    let request = [ADDRESS, 6, 0, 12, 0, 0];
+    assert_eq!(simulation.request(&request), [with_crc(&request)]);
+    assert!(simulation.outputs.get_override(OutputId::MagnetValveBuffer) == Override::Auto);
+}
+
+#[test]
+fn write_loading_differences_past_each_other() {
+    let mut simulation = Simulation::new();
+    assert_eq!(simulation.loading(), (80, 30));
+
+    // Each value alone would stop the loading before it is started
+    assert_eq!(
+        simulation.request(&[ADDRESS, 16, 0, 4, 0, 2, 4, 0, 20, 0, 10]),
+        [with_crc(&[ADDRESS, 16, 0, 4, 0, 2])]
+    );
+    assert_eq!(simulation.loading(), (20, 10));
+
+    assert_eq!(
+        simulation.request(&[ADDRESS, 16, 0, 4, 0, 2, 4, 0, 90, 0, 40]),
+        [with_crc(&[ADDRESS, 16, 0, 4, 0, 2])]
+    );
+    assert_eq!(simulation.loading(), (90, 40));
+
+    // The combination is still checked
+    assert_eq!(
+        simulation.request(&[ADDRESS, 16, 0, 4, 0, 2, 4, 0, 20, 0, 30]),
+        [with_crc(&[ADDRESS, 16 | 0x80, 3])]
+    );
+    assert_eq!(simulation.loading(), (90, 40));
+    assert_eq!(
+        simulation.request(&[ADDRESS, 6, 0, 5, 0, 90]),
+        [with_crc(&[ADDRESS, 6 | 0x80, 3])]
+    );
+    assert_eq!(simulation.loading(), (90, 40));
+}
+
+#[test]
+fn exceptions() {
+    let mut simulation = Simulation::new();
+
+    // Unknown function
+    assert_eq!(
+        simulation.request(&[ADDRESS, 8, 0, 0, 0, 0]),
+        [with_crc(&[ADDRESS, 0x88, 1])]
+    );
+    // Read past the last temperature
+    assert_eq!(
+        simulation.request(&[ADDRESS, 4, 0, 2, 0, 3]),
+        [with_crc(&[ADDRESS, 0x84, 2])]
+    );
+    // Read past the last override
+    assert_eq!(
+        simulation.request(&[ADDRESS, 3, 0, 12, 0, 3]),
+        [with_crc(&[ADDRESS, 0x83, 2])]
+    );
+    // Read more registers than fit into a response
+    assert_eq!(
+        simulation.request(&[ADDRESS, 3, 0, 0, 0, 40]),
+        [with_crc(&[ADDRESS, 0x83, 3])]
+    );
+    // Invalid coil value
+    assert_eq!(
+        simulation.request(&[ADDRESS, 5, 0, 0, 0x12, 0x34]),
+        [with_crc(&[ADDRESS, 0x85, 3])]
+    );
+    // The state is read only
+    assert_eq!(
+        simulation.request(&[ADDRESS, 6, 0, 0, 0, 1]),
+        [with_crc(&[ADDRESS, 0x86, 2])]
+    );
+    // Parameter out of range
+    assert_eq!(
+        simulation.request(&[ADDRESS, 6, 0, 1, 0, 101]),
+        [with_crc(&[ADDRESS, 0x86, 3])]
+    );
+    // Unknown override mode
+    assert_eq!(
+        simulation.request(&[ADDRESS, 6, 0, 11, 0, 3]),
+        [with_crc(&[ADDRESS, 0x86, 3])]
+    );
+}
+
+#[test]
+fn write_multiple_is_not_executed_partially() {
+    let mut simulation = Simulation::new();
+
+    // Includes the read only state
+    assert_eq!(
+        simulation.request(&[ADDRESS, 16, 0, 0, 0, 2, 4, 0, 1, 0, 100]),
+        [with_crc(&[ADDRESS, 0x90, 2])]
+    );
+    assert_eq!(simulation.parameters.buffer_hysteresis, 50);
+
+    // The override is valid, the hold time before it out of range
+    assert_eq!(
+        simulation.request(&[ADDRESS, 16, 0, 9, 0, 3, 6, 0x02, 0x6C, 0, 200, 0, 1]),
+        [with_crc(&[ADDRESS, 0x90, 3])]
+    );
+    assert_eq!(simulation.parameters.legionella_temperature, 600);
+    assert!(simulation.outputs.get_override(OutputId::BurnerInhibit) == Override::Auto);
+
+    // The parameters are valid, the override after them is not
+    assert_eq!(
+        simulation.request(&[ADDRESS, 16, 0, 9, 0, 3, 6, 0x02, 0x6C, 0, 45, 0, 7]),
+        [with_crc(&[ADDRESS, 0x90, 3])]
+    );
+    assert_eq!(simulation.parameters.legionella_temperature, 600);
+    assert_eq!(simulation.parameters.legionella_hold_time, 30);
+
+    assert_eq!(
+        simulation.request(&[ADDRESS, 16, 0, 9, 0, 3, 6, 0x02, 0x6C, 0, 45, 0, 2]),
+        [with_crc(&[ADDRESS, 16, 0, 9, 0, 3])]
+    );
+    assert_eq!(simulation.parameters.legionella_temperature, 620);
+    assert_eq!(simulation.parameters.legionella_hold_time, 45);
+    assert!(simulation.outputs.get_override(OutputId::BurnerInhibit) == Override::ForceOff);
+}
+
+#[test]
+fn ignored_requests() {
+    let mut simulation = Simulation::new();
+
+    // Other slave
+    assert!(simulation.request(&[2, 3, 0, 0, 0, 1]).is_empty());
+
+    // Corrupted crc
+    let mut request = with_crc(&[ADDRESS, 3, 0, 0, 0, 1]);
+    request[3] ^= 0x01;
+    simulation.receive(&request);
+    simulation.wait(10);
+    assert!(simulation.take_responses().is_empty());
+
+    // Broadcasts are executed but not answered
+    assert!(simulation.request(&[0, 6, 0, 8, 0, 42]).is_empty());
+    assert_eq!(simulation.parameters.temperature_deadband, 42);
+
+    // Longer than the buffer
+    let mut long = vec![ADDRESS, 16, 0, 0, 0, 30, 60];
+    long.resize(7 + 60, 0);
+    assert!(simulation.request(&long).is_empty());
+    // The next request is received normally
+    assert_eq!(simulation.request(&[ADDRESS, 3, 0, 2, 0, 1]).len(), 1);
+}
+
+#[test]
+fn frames_are_split_by_silence() {
+    let mut simulation = Simulation::new();
+    let request = with_crc(&[ADDRESS, 4, 0, 0, 0, 1]);
+
+    // A short pause inside a frame does not end it
+    simulation.receive(&request[..3]);
+    simulation.wait(2);
+    simulation.receive(&request[3..]);
+    simulation.wait(10);
+    assert_eq!(simulation.take_responses().len(), 1);
+
+    // Two requests with a gap of 3.5 characters are answered separately
+    simulation.receive(&request);
+    simulation.wait(4);
+    simulation.receive(&request);
+    simulation.wait(10);
+    assert_eq!(simulation.take_responses().len(), 2);
+
+    // Without the gap they are one invalid frame
+    let mut requests = request.clone();
+    requests.extend_from_slice(&request);
+    simulation.receive(&requests);
+    simulation.wait(10);
+    assert!(simulation.take_responses().is_empty());
+}
+
+#[test]
+fn frames_are_split_when_polled_late() {
+    let mut simulation = Simulation::new();
+    let other = with_crc(&[2, 4, 0, 0, 0, 1]);
+    let request = with_crc(&[ADDRESS, 4, 0, 0, 0, 1]);
+
+    // Request to another slave and to us while a task blocks the main loop for 100 ms
+    simulation.polling = false;
+    simulation.receive(&other);
+    simulation.wait(4);
+    simulation.receive(&request);
+    simulation.wait(100 - 2 * 8 - 4);
+    simulation.polling = true;
+    simulation.wait(10);
+    assert_eq!(simulation.take_responses().len(), 1);
+
+    // The next request follows directly after the task
+    simulation.polling = false;
+    simulation.receive(&request);
+    simulation.wait(4);
+    simulation.receive(&request);
+    simulation.polling = true;
+    simulation.wait(10);
+    assert_eq!(simulation.take_responses().len(), 2);
+
+    // Without the gap they are still one invalid frame
+    simulation.polling = false;
+    let mut requests = request.clone();
+    requests.extend_from_slice(&request);
+    simulation.receive(&requests);
+    simulation.polling = true;
+    simulation.wait(10);
+    assert!(simulation.take_responses().is_empty());
+}
@@ -0,0 +1,508 @@
+//! Decoder for the binary telemetry of the controller (feature `binary` of the firmware)
+//!
+//! Every message is COBS encoded and terminated by a zero byte. The decoded message is
+//!
+//! `<id> <sequence u16> <payload> <crc u16>`
+//!
+//! with all numbers little endian and a CRC-16/CCITT-FALSE over id, sequence and payload.
+
+use crate::{crc, Error, Kind};
+
+/// Message ids and bits as send by the firmware
+#[allow(dead_code)]
+#[path = "../../../src/binary.rs"]
+mod firmware;
+
+use firmware::{
+    INPUT_HEATING_PUMP, INPUT_START_BURNER, INPUT_WARM_WATER_PUMP, OUTPUT_BURNER_INHIBIT,
+    OUTPUT_MAGNET_VALVE_BUFFER, OUTPUT_PUMP_BUFFER,
+};
+pub use firmware::{IO, MISSING_TEMPERATURE, STATE, TEMPERATURES, TEXT};
+use heat_control_statemachine::STATE_NAMES;
+
+/// Longest accepted frame without the delimiter. The messages of the controller fit into a
+/// single COBS block
+const MAX_FRAME_SIZE: usize = 255;
+
+/// Name of a state as used in the text protocol
+pub fn state_name(state: u8) -> Option<&'static str> {
+    STATE_NAMES.get(state as usize).copied()
+}
+
+/// Temperatures in 1/10 °C
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Temperatures {
+    pub buffer_top: Option<i16>,
+    pub buffer_bottom: Option<i16>,
+    pub warm_water: Option<i16>,
+    pub boiler: Option<i16>,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Inputs {
+    pub start_burner: bool,
+    pub warm_water_pump: bool,
+    pub heating_pump: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Outputs {
+    pub burner_inhibit: bool,
+    pub magnet_valve_buffer: bool,
+    pub pump_buffer: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Temperatures(Temperatures),
+    Io(Inputs, Outputs),
+    /// Id of the state, see `state_name`
+    State(u8),
+    /// Message of the text protocol
+    Text(Kind, String),
+}
+
+/// A checked message with its sequence number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub sequence: u16,
+    pub message: Message,
+}
+
+/// COBS encode data. The zero delimiter is not added
+pub fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + data.len() / 254 + 1);
+    let mut code_index = 0;
+    encoded.push(0);
+    let mut code = 1_u8;
+
+    for byte in data {
+        if *byte == 0 {
+            encoded[code_index] = code;
+            code_index = encoded.len();
+            encoded.push(0);
+            code = 1;
+        } else {
+            encoded.push(*byte);
+            code += 1;
+            if code == 0xFF {
+                encoded[code_index] = code;
+                code_index = encoded.len();
+                encoded.push(0);
+                code = 1;
+            }
+        }
+    }
+    encoded[code_index] = code;
+    encoded
+}
+
+/// Decode COBS data without the zero delimiter
+pub fn cobs_decode(encoded: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+    while index < encoded.len() {
+        let code = encoded[index] as usize;
+        if code == 0 || index + code > encoded.len() {
+            return Err(Error::Format);
+        }
+        let block = &encoded[index + 1..index + code];
+        if block.contains(&0) {
+            return Err(Error::Format);
+        }
+        data.extend_from_slice(block);
+        index += code;
+        // Blocks shorter than the maximum are followed by a zero, except the last one
+        if code < 0xFF && index < encoded.len() {
+            data.push(0);
+        }
+    }
+    Ok(data)
+}
+
+/// Decode a received frame without the zero delimiter
+pub fn decode(frame: &[u8]) -> Result<Packet, Error> {
+    let data = cobs_decode(frame)?;
+    if data.len() < 5 {
+        return Err(Error::Format);
+    }
+    let (content, received) = data.split_at(data.len() - 2);
+    let received = u16::from_le_bytes([received[0], received[1]]);
+    let expected = crc(content);
+    if expected != received {
+        return Err(Error::Crc { expected, received });
+    }
+
+    let sequence = u16::from_le_bytes([content[1], content[2]]);
+    let payload = &content[3..];
+    let message = match (content[0], payload) {
+        (TEMPERATURES, payload) if payload.len() == 8 => {
+            let temperature = |index: usize| {
+                let value = i16::from_le_bytes([payload[2 * index], payload[2 * index + 1]]);
+                if value == MISSING_TEMPERATURE {
+                    None
+                } else {
+                    Some(value)
+                }
+            };
+            Message::Temperatures(Temperatures {
+                buffer_top: temperature(0),
+                buffer_bottom: temperature(1),
+                warm_water: temperature(2),
+                boiler: temperature(3),
+            })
+        }
+        (IO, [inputs, outputs]) => Message::Io(
+            Inputs {
+                start_burner: inputs & INPUT_START_BURNER != 0,
+                warm_water_pump: inputs & INPUT_WARM_WATER_PUMP != 0,
+                heating_pump: inputs & INPUT_HEATING_PUMP != 0,
+            },
+            Outputs {
+                burner_inhibit: outputs & OUTPUT_BURNER_INHIBIT != 0,
+                magnet_valve_buffer: outputs & OUTPUT_MAGNET_VALVE_BUFFER != 0,
+                pump_buffer: outputs & OUTPUT_PUMP_BUFFER != 0,
+            },
+        ),
+        (STATE, [state]) => Message::State(*state),
+        (TEXT, [kind, text @ ..]) => {
+            let kind = Kind::from_char(*kind as char).ok_or(Error::Format)?;
+            Message::Text(kind, String::from_utf8_lossy(text).into_owned())
+        }
+        (TEMPERATURES | IO | STATE | TEXT, _) => return Err(Error::Format),
+        (id, _) => return Err(Error::Message(id)),
+    };
+
+    Ok(Packet { sequence, message })
+}
+
+/// Encode a packet the same way as the controller. The zero delimiter is added
+pub fn encode(packet: &Packet) -> Vec<u8> {
+    let mut data = Vec::new();
+    let bits = |values: &[(bool, u8)]| {
+        values
+            .iter()
+            .filter(|(value, _)| *value)
+            .fold(0, |bits, (_, bit)| bits | bit)
+    };
+    match &packet.message {
+        Message::Temperatures(temperatures) => {
+            data.push(TEMPERATURES);
+            data.extend_from_slice(&packet.sequence.to_le_bytes());
+            for temperature in [
+                temperatures.buffer_top,
+                temperatures.buffer_bottom,
+                temperatures.warm_water,
+                temperatures.boiler,
+            ] {
+                let temperature = temperature.unwrap_or(MISSING_TEMPERATURE);
+                data.extend_from_slice(&temperature.to_le_bytes());
+            }
+        }
+        Message::Io(inputs, outputs) => {
+            data.push(IO);
+            data.extend_from_slice(&packet.sequence.to_le_bytes());
+            data.push(bits(&[
+                (inputs.start_burner, INPUT_START_BURNER),
+                (inputs.warm_water_pump, INPUT_WARM_WATER_PUMP),
+                (inputs.heating_pump, INPUT_HEATING_PUMP),
+            ]));
+            data.push(bits(&[
+                (outputs.burner_inhibit, OUTPUT_BURNER_INHIBIT),
+                (outputs.magnet_valve_buffer, OUTPUT_MAGNET_VALVE_BUFFER),
+                (outputs.pump_buffer, OUTPUT_PUMP_BUFFER),
+            ]));
+        }
+        Message::State(state) => {
+            data.push(STATE);
+            data.extend_from_slice(&packet.sequence.to_le_bytes());
+            data.push(*state);
+        }
+        Message::Text(kind, text) => {
+            data.push(TEXT);
+            data.extend_from_slice(&packet.sequence.to_le_bytes());
+            data.push(kind.to_char() as u8);
+            data.extend_from_slice(text.as_bytes());
+        }
+    }
+    data.extend_from_slice(&crc(&data).to_le_bytes());
+
+    let mut frame = cobs_encode(&data);
+    frame.push(0);
+    frame
+}
+
+/// Splits a byte stream at the zero delimiters and decodes the frames
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// The current frame is longer than `MAX_FRAME_SIZE` and is dropped
+    overflow: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a received byte. Returns the decoded packet if a frame is complete. Frames longer
+    /// than any message of the controller are dropped as invalid
+    pub fn push(&mut self, byte: u8) -> Option<Result<Packet, Error>> {
+        if byte != 0 {
+            if self.buffer.len() < MAX_FRAME_SIZE {
+                self.buffer.push(byte);
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let result = if self.overflow {
+            Err(Error::Format)
+        } else {
+            decode(&self.buffer)
+        };
+        self.buffer.clear();
+        self.overflow = false;
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) {
+        let packet = Packet {
+            sequence: 0x1234,
+            message,
+        };
+        let frame = encode(&packet);
+        assert_eq!(frame.last(), Some(&0));
+        assert!(!frame[..frame.len() - 1].contains(&0));
+
+        let mut decoder = Decoder::new();
+        let mut decoded = Vec::new();
+        for byte in frame {
+            if let Some(result) = decoder.push(byte) {
+                decoded.push(result.unwrap());
+            }
+        }
+        assert_eq!(decoded, vec![packet]);
+    }
+
+    #[test]
+    fn temperatures_round_trip() {
+        round_trip(Message::Temperatures(Temperatures {
+            buffer_top: Some(553),
+            buffer_bottom: Some(-12),
+            warm_water: None,
+            boiler: Some(0),
+        }));
+    }
+
+    #[test]
+    fn io_round_trip() {
+        round_trip(Message::Io(
+            Inputs {
+                start_burner: true,
+                warm_water_pump: false,
+                heating_pump: true,
+            },
+            Outputs {
+                burner_inhibit: false,
+                magnet_valve_buffer: true,
+                pump_buffer: false,
+            },
+        ));
+        round_trip(Message::Io(Inputs::default(), Outputs::default()));
+    }
+
+    #[test]
+    fn state_round_trip() {
+        round_trip(Message::State(0));
+        round_trip(Message::State(13));
+        assert_eq!(state_name(13), Some("Pump Stopping"));
+        assert_eq!(state_name(14), None);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        round_trip(Message::Text(Kind::Mqtt, "State:=Init".to_string()));
+        round_trip(Message::Text(Kind::Reply, String::new()));
+    }
+
+    #[test]
+    fn cobs_round_trip() {
+        let long: Vec<u8> = (0..600).map(|value| (value % 256) as u8).collect();
+        let cases: [&[u8]; 6] = [&[], &[0], &[0, 0], &[1, 0, 2], &[1; 254], &long];
+        for data in cases {
+            let encoded = cobs_encode(data);
+            assert!(!encoded.contains(&0));
+            assert_eq!(cobs_decode(&encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn corrupted_frame_is_rejected() {
+        let mut frame = encode(&Packet {
+            sequence: 1,
+            message: Message::State(3),
+        });
+        frame[2] ^= 0x40;
+        let mut decoder = Decoder::new();
+        let results: Vec<_> = frame
+            .into_iter()
+            .filter_map(|byte| decoder.push(byte))
+            .collect();
+        assert!(matches!(results[..], [Err(Error::Crc { .. })]));
+    }
+
+    #[test]
+    fn unknown_message_is_rejected() {
+        let mut data = vec![0x7F, 0, 0];
+        data.extend_from_slice(&crc(&data).to_le_bytes());
+        assert_eq!(decode(&cobs_encode(&data)), Err(Error::Message(0x7F)));
+    }
+
+    /// Build a message like the serial logger of the firmware and encode it with the firmware
+    fn firmware_frame(id: u8, sequence: u16, payload: &[u8]) -> Vec<u8> {
+        let mut buffer = firmware::Buffer::new();
+        let mut content = Vec::new();
+        content.extend_from_slice(buffer.push(&[id]));
+        content.extend_from_slice(buffer.push(&sequence.to_le_bytes()));
+        content.extend_from_slice(buffer.push(payload));
+        let message = buffer.finish(crc(&content));
+
+        let mut frame = Vec::new();
+        firmware::write_cobs(message, |byte| frame.push(byte));
+        frame
+    }
+
+    fn decode_stream(stream: &[u8]) -> Vec<Result<Packet, Error>> {
+        let mut decoder = Decoder::new();
+        stream
+            .iter()
+            .filter_map(|byte| decoder.push(*byte))
+            .collect()
+    }
+
+    #[test]
+    fn firmware_messages_are_decoded() {
+        let mut temperatures = Vec::new();
+        for temperature in [553_i16, 0, MISSING_TEMPERATURE, -12] {
+            temperatures.extend_from_slice(&temperature.to_le_bytes());
+        }
+        let mut stream = firmware_frame(firmware::TEMPERATURES, 0x0100, &temperatures);
+        stream.extend(firmware_frame(
+            firmware::IO,
+            0x0101,
+            &[
+                firmware::INPUT_HEATING_PUMP,
+                firmware::OUTPUT_BURNER_INHIBIT | firmware::OUTPUT_PUMP_BUFFER,
+            ],
+        ));
+        stream.extend(firmware_frame(firmware::STATE, 0, &[0]));
+        stream.extend(firmware_frame(firmware::TEXT, 0xFFFF, b"MState:=Init"));
+
+        let packets: Vec<_> = decode_stream(&stream)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            packets,
+            [
+                Packet {
+                    sequence: 0x0100,
+                    message: Message::Temperatures(Temperatures {
+                        buffer_top: Some(553),
+                        buffer_bottom: Some(0),
+                        warm_water: None,
+                        boiler: Some(-12),
+                    }),
+                },
+                Packet {
+                    sequence: 0x0101,
+                    message: Message::Io(
+                        Inputs {
+                            start_burner: false,
+                            warm_water_pump: false,
+                            heating_pump: true,
+                        },
+                        Outputs {
+                            burner_inhibit: true,
+                            magnet_valve_buffer: false,
+                            pump_buffer: true,
+                        },
+                    ),
+                },
+                Packet {
+                    sequence: 0,
+                    message: Message::State(0),
+                },
+                Packet {
+                    sequence: 0xFFFF,
+                    message: Message::Text(Kind::Mqtt, "State:=Init".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn firmware_matches_host_encoder() {
+        let packet = Packet {
+            sequence: 0x3400,
+            message: Message::State(7),
+        };
+        assert_eq!(
+            firmware_frame(firmware::STATE, 0x3400, &[7]),
+            encode(&packet)
+        );
+    }
+
+    #[test]
+    fn firmware_cuts_long_text() {
+        let text = [b'x'; 200];
+        let mut payload = vec![b'I'];
+        payload.extend_from_slice(&text);
+        let packets = decode_stream(&firmware_frame(firmware::TEXT, 1, &payload));
+        match &packets[..] {
+            [Ok(Packet {
+                message: Message::Text(Kind::Info, text),
+                ..
+            })] => {
+                // Id, sequence, type and crc take 6 bytes of the 80 byte message
+                assert_eq!(text.len(), 74);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overlong_frame_is_dropped() {
+        let mut stream = vec![0x55; 10_000];
+        stream.push(0);
+        stream.extend(firmware_frame(firmware::STATE, 2, &[1]));
+
+        let mut decoder = Decoder::new();
+        let mut results = Vec::new();
+        for byte in stream {
+            if let Some(result) = decoder.push(byte) {
+                results.push(result);
+            }
+            assert!(decoder.buffer.len() <= MAX_FRAME_SIZE);
+        }
+        assert_eq!(
+            results,
+            [
+                Err(Error::Format),
+                Ok(Packet {
+                    sequence: 2,
+                    message: Message::State(1),
+                }),
+            ]
+        );
+    }
+}
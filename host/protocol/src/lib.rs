@@ -0,0 +1,336 @@
+//! Parser for the framed serial protocol of the heat control
+//!
+//! Every message of the controller is one line:
+//!
+//! `#<version>;<sequence>;<type>;<payload>*<crc>`
+//!
+//! The CRC is a CRC-16/CCITT-FALSE over everything between `#` and `*`. Lines without the frame
+//! markers (e.g. from the panic handler) are reported as plain text.
+
+use crc_any::CRCu16;
+
+pub mod binary;
+
+/// Supported version of the frame format
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Info message send by the controller after a restart
+pub const STARTUP_MESSAGE: &str = "Heat Control Init";
+/// MQTT topic with the reset cause, send by the controller after a restart
+pub const RESET_CAUSE_TOPIC: &str = "Reset/Ursache";
+
+/// Type of a frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Mqtt,
+    Json,
+    Announce,
+    Reply,
+}
+
+impl Kind {
+    /// All kinds in the order of their characters
+    const KINDS: [(Kind, char); 8] = [
+        (Kind::Debug, 'D'),
+        (Kind::Info, 'I'),
+        (Kind::Warn, 'W'),
+        (Kind::Error, 'E'),
+        (Kind::Mqtt, 'M'),
+        (Kind::Json, 'J'),
+        (Kind::Announce, 'A'),
+        (Kind::Reply, 'R'),
+    ];
+
+    pub fn from_char(kind: char) -> Option<Self> {
+        Self::KINDS
+            .iter()
+            .find(|(_, c)| *c == kind)
+            .map(|(kind, _)| *kind)
+    }
+
+    pub fn to_char(self) -> char {
+        Self::KINDS
+            .iter()
+            .find(|(kind, _)| *kind == self)
+            .map(|(_, c)| *c)
+            .unwrap_or('?')
+    }
+
+    fn from_str(kind: &str) -> Option<Self> {
+        let mut chars = kind.chars();
+        match (chars.next(), chars.next()) {
+            (Some(kind), None) => Self::from_char(kind),
+            _ => None,
+        }
+    }
+}
+
+/// A checked frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub sequence: u16,
+    pub kind: Kind,
+    pub payload: String,
+}
+
+impl Frame {
+    /// Split the payload of MQTT and discovery frames to topic and value
+    pub fn topic_value(&self) -> Option<(&str, &str)> {
+        match self.kind {
+            Kind::Mqtt | Kind::Announce => self.payload.split_once(":="),
+            _ => None,
+        }
+    }
+
+    /// Check if the controller sends this frame after a restart
+    pub fn is_restart(&self) -> bool {
+        is_restart(self.kind, &self.payload)
+    }
+}
+
+/// Check if the controller sends a message after a restart. Its sequence numbers start at 0
+/// again in this case. The reset cause is checked as well, in case info messages are switched off
+pub fn is_restart(kind: Kind, payload: &str) -> bool {
+    match kind {
+        Kind::Info => payload == STARTUP_MESSAGE,
+        Kind::Mqtt => payload
+            .split_once(":=")
+            .is_some_and(|(topic, _)| topic == RESET_CAUSE_TOPIC),
+        _ => false,
+    }
+}
+
+/// A received line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Frame(Frame),
+    /// Line without framing
+    Plain(String),
+}
+
+/// Errors of a line that looks like a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Fields are missing or invalid
+    Format,
+    /// Frame was send with a version this parser does not know
+    Version(u8),
+    /// Checksum does not match the content
+    Crc { expected: u16, received: u16 },
+    /// Binary message with an unknown id
+    Message(u8),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Format => write!(f, "invalid frame format"),
+            Error::Version(version) => write!(f, "unsupported protocol version {}", version),
+            Error::Crc { expected, received } => write!(
+                f,
+                "checksum mismatch, expected {:04X}, received {:04X}",
+                expected, received
+            ),
+            Error::Message(id) => write!(f, "unknown message id {:#04X}", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Checksum of the frame content
+pub fn crc(content: &[u8]) -> u16 {
+    let mut crc = CRCu16::crc16ccitt_false();
+    crc.digest(content);
+    crc.get_crc()
+}
+
+/// Parse a received line. Line endings are ignored
+pub fn parse_line(line: &str) -> Result<Line, Error> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let content = match line.strip_prefix('#') {
+        Some(content) => content,
+        None => return Ok(Line::Plain(line.to_string())),
+    };
+
+    let (content, received) = content.rsplit_once('*').ok_or(Error::Format)?;
+    if received.len() != 4 {
+        return Err(Error::Format);
+    }
+    let received = u16::from_str_radix(received, 16).map_err(|_| Error::Format)?;
+    let expected = crc(content.as_bytes());
+    if expected != received {
+        return Err(Error::Crc { expected, received });
+    }
+
+    let mut fields = content.splitn(4, ';');
+    let version: u8 = next_field(&mut fields)?;
+    if version != PROTOCOL_VERSION {
+        return Err(Error::Version(version));
+    }
+    let sequence: u16 = next_field(&mut fields)?;
+    let kind = fields
+        .next()
+        .and_then(Kind::from_str)
+        .ok_or(Error::Format)?;
+    let payload = fields.next().ok_or(Error::Format)?.to_string();
+
+    Ok(Line::Frame(Frame {
+        sequence,
+        kind,
+        payload,
+    }))
+}
+
+fn next_field<'a, T: std::str::FromStr>(
+    fields: &mut impl Iterator<Item = &'a str>,
+) -> Result<T, Error> {
+    fields
+        .next()
+        .and_then(|field| field.parse().ok())
+        .ok_or(Error::Format)
+}
+
+/// Detects lost frames from the sequence numbers
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last: Option<u16>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a received frame. Returns the number of frames lost before it
+    pub fn update(&mut self, sequence: u16) -> u16 {
+        let lost = match self.last {
+            Some(last) => sequence.wrapping_sub(last).wrapping_sub(1),
+            None => 0,
+        };
+        self.last = Some(sequence);
+        lost
+    }
+
+    /// Forget the last sequence number, e.g. after the controller restarted
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame a content like the controller
+    fn frame(content: &str) -> String {
+        format!("#{}*{:04X}\n", content, crc(content.as_bytes()))
+    }
+
+    #[test]
+    fn valid_frame() {
+        assert_eq!(
+            parse_line(&frame("1;42;M;Temperature/Kessel:=55.3")),
+            Ok(Line::Frame(Frame {
+                sequence: 42,
+                kind: Kind::Mqtt,
+                payload: "Temperature/Kessel:=55.3".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn plain_line() {
+        assert_eq!(
+            parse_line("PANIC src/main.rs:12\r\n"),
+            Ok(Line::Plain("PANIC src/main.rs:12".to_string()))
+        );
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let line = frame("1;42;I;Init Done").replace("Done", "Dune");
+        assert!(matches!(parse_line(&line), Err(Error::Crc { .. })));
+    }
+
+    #[test]
+    fn star_in_payload() {
+        let line = frame("1;7;I;a*b*c");
+        match parse_line(&line) {
+            Ok(Line::Frame(frame)) => assert_eq!(frame.payload, "a*b*c"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_version() {
+        assert_eq!(
+            parse_line(&frame("2;1;I;Init Done")),
+            Err(Error::Version(2))
+        );
+    }
+
+    #[test]
+    fn malformed_fields() {
+        for content in [
+            "1;65536;I;Init Done",
+            "1;-1;I;Init Done",
+            "1;;I;Init Done",
+            "1;3;X;Init Done",
+            "1;3;II;Init Done",
+            "1;3;I",
+        ] {
+            assert_eq!(
+                parse_line(&frame(content)),
+                Err(Error::Format),
+                "{}",
+                content
+            );
+        }
+        assert_eq!(parse_line("#1;3;I;Init Done*12"), Err(Error::Format));
+        assert_eq!(parse_line("#1;3;I;Init Done"), Err(Error::Format));
+    }
+
+    #[test]
+    fn lost_frames() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.update(10), 0);
+        assert_eq!(tracker.update(11), 0);
+        assert_eq!(tracker.update(14), 2);
+    }
+
+    #[test]
+    fn lost_frames_across_wrap() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.update(65534), 0);
+        assert_eq!(tracker.update(65535), 0);
+        assert_eq!(tracker.update(0), 0);
+        assert_eq!(tracker.update(65533), 65532);
+
+        let mut tracker = SequenceTracker::new();
+        tracker.update(65530);
+        assert_eq!(tracker.update(3), 8);
+    }
+
+    #[test]
+    fn restart() {
+        let startup = Frame {
+            sequence: 0,
+            kind: Kind::Info,
+            payload: STARTUP_MESSAGE.to_string(),
+        };
+        assert!(startup.is_restart());
+        assert!(is_restart(Kind::Mqtt, "Reset/Ursache:=Watchdog"));
+        assert!(!is_restart(Kind::Mqtt, "Reset/Anzahl/Watchdog:=3"));
+        assert!(!is_restart(Kind::Info, "Init Done"));
+
+        let mut tracker = SequenceTracker::new();
+        tracker.update(1234);
+        tracker.reset();
+        assert_eq!(tracker.update(0), 0);
+    }
+}
@@ -0,0 +1,10 @@
+//! Report by exception of the firmware, to test deadband and keep alive
+
+#[path = "../../../src/time.rs"]
+mod time;
+
+#[path = "../../../src/report.rs"]
+mod report;
+
+pub use report::*;
+pub use time::{Duration, Instant};
@@ -0,0 +1,262 @@
+//! Telemetry cycles of the controller, one per second
+
+use heat_control_report::{Duration, Instant, Part, Reporter, Value};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(300);
+const DEADBAND: i16 = 5;
+/// Outputs and tasks of the controller
+const OUTPUTS: usize = 3;
+const TASKS: usize = 5;
+
+/// Reporter after the start of the first cycle, which publishes everything
+fn started(time: Instant) -> Reporter {
+    let mut reporter = Reporter::new(KEEP_ALIVE, OUTPUTS, TASKS);
+    assert_eq!(reporter.start(time), Some(Part::Temperatures));
+    reporter
+}
+
+#[cfg(not(feature = "binary"))]
+#[test]
+fn temperature_deadband() {
+    let mut reporter = started(Instant::from_millis(0));
+    assert!(reporter.temperature(Value::Boiler, Some(500), DEADBAND));
+
+    let mut time = Instant::from_millis(0);
+    let mut cycle = |reporter: &mut Reporter, temperature| {
+        time = time + Duration::from_secs(1);
+        assert_ne!(reporter.start(time), Some(Part::Temperatures));
+        reporter.temperature(Value::Boiler, Some(temperature), DEADBAND)
+    };
+
+    // Just below the deadband in both directions
+    assert!(!cycle(&mut reporter, 504));
+    assert!(!cycle(&mut reporter, 496));
+    // Compared to the published value, not to the last measured one
+    assert!(!cycle(&mut reporter, 503));
+    assert!(cycle(&mut reporter, 505));
+    assert!(!cycle(&mut reporter, 501));
+    assert!(cycle(&mut reporter, 500));
+    assert!(cycle(&mut reporter, 495));
+}
+
+#[cfg(not(feature = "binary"))]
+#[test]
+fn missing_temperature() {
+    let mut reporter = started(Instant::from_millis(0));
+    assert!(reporter.temperature(Value::BufferTop, Some(500), DEADBAND));
+
+    reporter.start(Instant::from_millis(1_000));
+    assert!(reporter.temperature(Value::BufferTop, None, DEADBAND));
+    reporter.start(Instant::from_millis(2_000));
+    assert!(!reporter.temperature(Value::BufferTop, None, DEADBAND));
+    // Present again with the same value as before
+    reporter.start(Instant::from_millis(3_000));
+    assert!(reporter.temperature(Value::BufferTop, Some(500), DEADBAND));
+}
+
+#[cfg(not(feature = "binary"))]
+#[test]
+fn missing_temperature_at_startup() {
+    let mut reporter = started(Instant::from_millis(0));
+    assert!(reporter.temperature(Value::WarmWater, None, DEADBAND));
+    reporter.start(Instant::from_millis(1_000));
+    assert!(!reporter.temperature(Value::WarmWater, None, DEADBAND));
+    reporter.start(Instant::from_millis(2_000));
+    assert!(reporter.temperature(Value::WarmWater, Some(i16::MIN + 1), DEADBAND));
+}
+
+#[test]
+fn flags_and_values() {
+    let mut reporter = started(Instant::from_millis(0));
+    assert!(reporter.flag(Value::PumpBuffer, false));
+    assert!(reporter.value(Value::State, 3));
+
+    reporter.start(Instant::from_millis(1_000));
+    assert!(!reporter.flag(Value::PumpBuffer, false));
+    assert!(!reporter.value(Value::State, 3));
+
+    reporter.start(Instant::from_millis(2_000));
+    assert!(reporter.flag(Value::PumpBuffer, true));
+    assert!(reporter.value(Value::State, 4));
+}
+
+#[test]
+fn keep_alive_parts() {
+    let mut reporter = started(Instant::from_millis(0));
+    let parts: Vec<_> = (1..14)
+        .map(|second| reporter.start(Instant::from_millis(second * 1_000)))
+        .collect();
+    assert_eq!(
+        parts,
+        [
+            Some(Part::Inputs),
+            Some(Part::Outputs),
+            Some(Part::Status),
+            Some(Part::Counters),
+            Some(Part::Switches(0)),
+            Some(Part::Switches(1)),
+            Some(Part::Switches(2)),
+            Some(Part::Task(0)),
+            Some(Part::Task(1)),
+            Some(Part::Task(2)),
+            Some(Part::Task(3)),
+            Some(Part::Task(4)),
+            Some(Part::Diagnostics),
+        ]
+    );
+    assert_eq!(reporter.start(Instant::from_millis(14_000)), None);
+    assert_eq!(reporter.start(Instant::from_millis(15_000)), None);
+
+    let mut reporter = Reporter::new(KEEP_ALIVE, 0, 0);
+    reporter.start(Instant::from_millis(0));
+    reporter.start(Instant::from_millis(1_000));
+    reporter.start(Instant::from_millis(2_000));
+    reporter.start(Instant::from_millis(3_000));
+    assert_eq!(
+        reporter.start(Instant::from_millis(4_000)),
+        Some(Part::Counters)
+    );
+    assert_eq!(
+        reporter.start(Instant::from_millis(5_000)),
+        Some(Part::Diagnostics)
+    );
+}
+
+#[test]
+fn values_are_published_with_their_part() {
+    let mut reporter = started(Instant::from_millis(0));
+    assert!(reporter.flag(Value::Display, true));
+    assert!(reporter.flag(Value::PumpBuffer, false));
+
+    let mut published = Vec::new();
+    for second in 1..6 {
+        let part = reporter.start(Instant::from_millis(second * 1_000));
+        if reporter.flag(Value::Display, true) {
+            published.push((part, Value::Display));
+        }
+        if reporter.flag(Value::PumpBuffer, false) {
+            published.push((part, Value::PumpBuffer));
+        }
+    }
+    assert_eq!(
+        published,
+        [
+            (Some(Part::Outputs), Value::PumpBuffer),
+            (Some(Part::Status), Value::Display),
+        ]
+    );
+}
+
+#[test]
+fn force_all() {
+    let mut reporter = started(Instant::from_millis(0));
+    for second in 1..20 {
+        reporter.start(Instant::from_millis(second * 1_000));
+    }
+    assert_eq!(reporter.start(Instant::from_millis(20_000)), None);
+
+    reporter.force_all();
+    assert_eq!(
+        reporter.start(Instant::from_millis(21_000)),
+        Some(Part::Temperatures)
+    );
+
+    // The keep alive is counted from the forced cycle
+    let forced = Instant::from_millis(21_000);
+    assert_ne!(
+        reporter.start(forced + KEEP_ALIVE - Duration::from_millis(1)),
+        Some(Part::Temperatures)
+    );
+    assert_eq!(
+        reporter.start(forced + KEEP_ALIVE),
+        Some(Part::Temperatures)
+    );
+}
+
+#[test]
+fn keep_alive_across_wrap() {
+    let first = Instant::from_millis(u32::MAX - 100_000);
+    let mut reporter = started(first);
+
+    // About 100 s before the wrap of the timer
+    let mut time = first;
+    for _ in 0..20 {
+        time = time + Duration::from_secs(1);
+        reporter.start(time);
+    }
+    let before = first + KEEP_ALIVE - Duration::from_millis(1);
+    assert!(before.as_millis() < first.as_millis());
+    assert_eq!(reporter.start(before), None);
+
+    assert_eq!(reporter.start(first + KEEP_ALIVE), Some(Part::Temperatures));
+}
+
+#[cfg(not(feature = "binary"))]
+#[test]
+fn snapshot() {
+    let temperatures = |boiler| {
+        [
+            (Value::BufferTop, Some(550)),
+            (Value::BufferBottom, Some(400)),
+            (Value::WarmWater, None),
+            (Value::Boiler, Some(boiler)),
+        ]
+    };
+    let io = |pump| {
+        [
+            (Value::StartBurner, false),
+            (Value::WarmWaterPump, false),
+            (Value::HeatingPump, true),
+            (Value::BurnerInhibit, false),
+            (Value::MagnetValveBuffer, pump),
+            (Value::PumpBuffer, pump),
+        ]
+    };
+
+    let mut reporter = started(Instant::from_millis(0));
+    assert!(reporter.snapshot(&temperatures(600), &io(false), 2, DEADBAND));
+
+    // Only the first part of the keep alive publishes the snapshot
+    reporter.start(Instant::from_millis(1_000));
+    assert!(!reporter.snapshot(&temperatures(604), &io(false), 2, DEADBAND));
+    reporter.start(Instant::from_millis(2_000));
+    assert!(reporter.snapshot(&temperatures(604), &io(true), 2, DEADBAND));
+    reporter.start(Instant::from_millis(3_000));
+    assert!(!reporter.snapshot(&temperatures(604), &io(true), 2, DEADBAND));
+    reporter.start(Instant::from_millis(4_000));
+    assert!(reporter.snapshot(&temperatures(604), &io(true), 4, DEADBAND));
+    reporter.start(Instant::from_millis(5_000));
+    assert!(reporter.snapshot(&temperatures(609), &io(true), 4, DEADBAND));
+}
+
+#[cfg(feature = "binary")]
+#[test]
+fn groups() {
+    let temperatures = |boiler| [(Value::BufferTop, Some(550)), (Value::Boiler, Some(boiler))];
+    let io = |start| [(Value::StartBurner, start), (Value::PumpBuffer, true)];
+
+    let mut reporter = started(Instant::from_millis(0));
+    assert!(reporter.temperatures(&temperatures(600), DEADBAND));
+    assert!(reporter.flags(&io(false)));
+
+    // The group is published with the parts of all its values
+    assert_eq!(
+        reporter.start(Instant::from_millis(1_000)),
+        Some(Part::Inputs)
+    );
+    assert!(!reporter.temperatures(&temperatures(604), DEADBAND));
+    assert!(reporter.flags(&io(false)));
+    assert_eq!(
+        reporter.start(Instant::from_millis(2_000)),
+        Some(Part::Outputs)
+    );
+    assert!(reporter.flags(&io(false)));
+
+    assert_eq!(
+        reporter.start(Instant::from_millis(3_000)),
+        Some(Part::Status)
+    );
+    assert!(!reporter.flags(&io(false)));
+    assert!(reporter.temperatures(&temperatures(595), DEADBAND));
+    assert!(reporter.flags(&io(true)));
+}
@@ -0,0 +1,14 @@
+//! Task scheduler of the firmware, to test periods, deadlines and triggers
+
+#[path = "../../../src/time.rs"]
+mod time;
+
+mod timer {
+    pub use crate::time::{Duration, Instant};
+}
+
+#[path = "../../../src/scheduler.rs"]
+mod scheduler;
+
+pub use scheduler::*;
+pub use time::{Duration, Instant};
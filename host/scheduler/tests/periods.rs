@@ -0,0 +1,249 @@
+//! Periods, deadlines and triggers with the task configuration of the main loop
+
+use heat_control_scheduler::{Duration, Instant, Scheduler, TaskConfig, TaskId};
+
+/// Shortly before the counter wraps
+const BEFORE_WRAP: Instant = Instant::from_millis(u32::MAX - 2_499);
+
+fn config(period: u32, deadline: u32) -> TaskConfig {
+    TaskConfig {
+        period: Duration::from_millis(period),
+        deadline: Duration::from_millis(deadline),
+    }
+}
+
+/// Sensors, control, display, telemetry and debug like in `main`
+fn scheduler(time: Instant) -> Scheduler {
+    Scheduler::new(
+        time,
+        [
+            config(1_000, 500),
+            config(1_000, 500),
+            config(10_000, 1_000),
+            config(1_000, 3_000),
+            config(10_000, 3_000),
+        ],
+    )
+}
+
+/// Run all due tasks without run time. Returns them in the order they ran
+fn run_due(scheduler: &mut Scheduler, time: Instant) -> Vec<TaskId> {
+    let mut tasks = Vec::new();
+    while let Some(task) = scheduler.poll(time) {
+        assert!(!scheduler.finish(time), "{:?} at {:?}", task, time);
+        tasks.push(task);
+    }
+    tasks
+}
+
+/// Poll the scheduler every `step` ms until `end` and return the start times of a task
+fn starts(
+    scheduler: &mut Scheduler,
+    start: Instant,
+    end: Duration,
+    step: u32,
+    id: TaskId,
+) -> Vec<Instant> {
+    let mut starts = Vec::new();
+    let mut elapsed = Duration::default();
+    while elapsed < end {
+        let time = start + elapsed;
+        if run_due(scheduler, time).contains(&id) {
+            starts.push(time);
+        }
+        elapsed = elapsed + Duration::from_millis(step);
+    }
+    starts
+}
+
+#[test]
+fn all_tasks_are_due_at_startup() {
+    let time = Instant::from_millis(0);
+    let mut scheduler = scheduler(time);
+
+    assert_eq!(
+        run_due(&mut scheduler, time),
+        [
+            TaskId::Sensors,
+            TaskId::Control,
+            TaskId::Display,
+            TaskId::Telemetry,
+            TaskId::Debug
+        ]
+    );
+    assert_eq!(
+        run_due(&mut scheduler, time + Duration::from_millis(999)),
+        []
+    );
+    assert_eq!(
+        run_due(&mut scheduler, time + Duration::from_secs(1)),
+        [TaskId::Sensors, TaskId::Control, TaskId::Telemetry]
+    );
+}
+
+#[test]
+fn late_polls_do_not_drift() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+
+    // Polled every 7 ms, so most runs start a few ms late
+    let starts = starts(
+        &mut scheduler,
+        start,
+        Duration::from_secs(100),
+        7,
+        TaskId::Control,
+    );
+    assert_eq!(starts.len(), 100);
+    for (second, time) in starts.iter().enumerate() {
+        let late = *time - (start + Duration::from_secs(second as u32));
+        assert!(late < Duration::from_millis(7), "{:?}", starts);
+    }
+}
+
+#[test]
+fn late_run_keeps_the_period() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    run_due(&mut scheduler, start);
+
+    // Late, but within the period
+    let late = start + Duration::from_millis(1_300);
+    assert!(run_due(&mut scheduler, late).contains(&TaskId::Control));
+    assert_eq!(
+        run_due(&mut scheduler, start + Duration::from_millis(1_999)),
+        []
+    );
+    assert!(run_due(&mut scheduler, start + Duration::from_secs(2)).contains(&TaskId::Control));
+}
+
+#[test]
+fn missed_periods_are_skipped_after_an_overrun() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    run_due(&mut scheduler, start);
+
+    // The sensors block the main loop for 3.5 s
+    let time = start + Duration::from_secs(1);
+    assert_eq!(scheduler.poll(time), Some(TaskId::Sensors));
+    let time = time + Duration::from_millis(3_500);
+    assert!(scheduler.finish(time));
+
+    // Every task runs once to catch up and missed its deadline
+    for task in [TaskId::Sensors, TaskId::Control, TaskId::Telemetry] {
+        assert_eq!(scheduler.poll(time), Some(task));
+        assert!(scheduler.finish(time));
+    }
+
+    // The period restarts with the late run instead of running the missed periods
+    assert_eq!(
+        run_due(&mut scheduler, time + Duration::from_millis(10)),
+        []
+    );
+    assert_eq!(
+        run_due(&mut scheduler, time + Duration::from_millis(999)),
+        []
+    );
+    assert_eq!(
+        run_due(&mut scheduler, time + Duration::from_secs(1)),
+        [TaskId::Sensors, TaskId::Control, TaskId::Telemetry]
+    );
+}
+
+#[test]
+fn deadline_is_counted_from_the_due_time() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    run_due(&mut scheduler, start);
+
+    // Control got due at 1 s and started 200 ms late
+    let due = start + Duration::from_secs(1);
+    assert_eq!(scheduler.poll(due), Some(TaskId::Sensors));
+    assert!(!scheduler.finish(due + Duration::from_millis(200)));
+    assert_eq!(
+        scheduler.poll(due + Duration::from_millis(200)),
+        Some(TaskId::Control)
+    );
+    assert!(!scheduler.finish(due + Duration::from_millis(500)));
+
+    let due = due + Duration::from_secs(1);
+    assert_eq!(scheduler.poll(due), Some(TaskId::Sensors));
+    assert!(!scheduler.finish(due + Duration::from_millis(200)));
+    assert_eq!(
+        scheduler.poll(due + Duration::from_millis(200)),
+        Some(TaskId::Control)
+    );
+    assert!(scheduler.finish(due + Duration::from_millis(501)));
+}
+
+#[test]
+fn trigger_runs_a_task_at_the_next_poll() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    run_due(&mut scheduler, start);
+
+    let trigger = start + Duration::from_millis(500);
+    scheduler.trigger(TaskId::Display);
+    scheduler.trigger(TaskId::Debug);
+    assert_eq!(
+        run_due(&mut scheduler, trigger),
+        [TaskId::Display, TaskId::Debug]
+    );
+    assert_eq!(run_due(&mut scheduler, trigger), []);
+
+    // The period restarts with the triggered run
+    let display = starts(
+        &mut scheduler,
+        trigger + Duration::from_millis(1),
+        Duration::from_secs(20),
+        1,
+        TaskId::Display,
+    );
+    assert_eq!(
+        display,
+        [
+            trigger + Duration::from_secs(10),
+            trigger + Duration::from_secs(20)
+        ]
+    );
+}
+
+#[test]
+fn triggered_task_has_the_deadline_from_the_trigger() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    run_due(&mut scheduler, start);
+
+    // Display is due by its period only at 10 s, the trigger starts it now
+    let trigger = start + Duration::from_millis(500);
+    scheduler.trigger(TaskId::Display);
+    assert_eq!(scheduler.poll(trigger), Some(TaskId::Display));
+    assert!(!scheduler.finish(trigger + Duration::from_secs(1)));
+}
+
+#[test]
+fn periods_across_the_wrap_of_the_timer() {
+    let mut scheduler = scheduler(BEFORE_WRAP);
+
+    let control = starts(
+        &mut scheduler,
+        BEFORE_WRAP,
+        Duration::from_secs(10),
+        1,
+        TaskId::Control,
+    );
+    let expected: Vec<_> = (0..10)
+        .map(|second| BEFORE_WRAP + Duration::from_secs(second))
+        .collect();
+    assert_eq!(control, expected);
+    assert!(control[3].as_millis() < BEFORE_WRAP.as_millis());
+
+    let display = starts(
+        &mut scheduler,
+        BEFORE_WRAP + Duration::from_secs(10),
+        Duration::from_secs(10),
+        1,
+        TaskId::Display,
+    );
+    assert_eq!(display, [BEFORE_WRAP + Duration::from_secs(10)]);
+}
@@ -0,0 +1,186 @@
+//! Run time statistics of the tasks and the load over the rolling window
+
+use heat_control_scheduler::{Duration, Instant, Scheduler, TaskConfig, TaskId};
+
+fn config(period: u32, deadline: u32) -> TaskConfig {
+    TaskConfig {
+        period: Duration::from_millis(period),
+        deadline: Duration::from_millis(deadline),
+    }
+}
+
+/// Control runs every second, the other tasks only once at startup
+fn scheduler(time: Instant) -> Scheduler {
+    let once = config(u32::MAX / 2, 1_000);
+    let mut scheduler = Scheduler::new(time, [once, config(1_000, 500), once, once, once]);
+    while scheduler.poll(time).is_some() {
+        scheduler.finish(time);
+    }
+    scheduler
+}
+
+/// Run control at the next second. Returns the end of the run and if it missed the deadline
+fn run_control(scheduler: &mut Scheduler, time: Instant, run_time: u32) -> (Instant, bool) {
+    assert_eq!(scheduler.poll(time), Some(TaskId::Control));
+    let end = time + Duration::from_millis(run_time);
+    (end, scheduler.finish(end))
+}
+
+/// Run control every second for `seconds` s starting at `start`. Returns the next second
+fn run_seconds(scheduler: &mut Scheduler, start: Instant, seconds: u32, run_time: u32) -> Instant {
+    for second in 0..seconds {
+        run_control(scheduler, start + Duration::from_secs(second), run_time);
+    }
+    start + Duration::from_secs(seconds)
+}
+
+#[test]
+fn min_max_and_average() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    let stats = scheduler.stats(TaskId::Display);
+    assert_eq!(stats.min, Duration::default());
+    assert_eq!(stats.max, Duration::default());
+    assert_eq!(stats.average(), Duration::default());
+
+    let mut time = start + Duration::from_secs(1);
+    for run_time in [30, 10, 20, 40] {
+        run_control(&mut scheduler, time, run_time);
+        time = time + Duration::from_secs(1);
+    }
+
+    let stats = scheduler.stats(TaskId::Control);
+    // The run at startup took no time
+    assert_eq!(stats.min, Duration::from_millis(0));
+    assert_eq!(stats.max, Duration::from_millis(40));
+    assert_eq!(stats.average(), Duration::from_millis(20));
+    assert_eq!(stats.overruns, 0);
+}
+
+#[test]
+fn min_of_the_first_run() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = Scheduler::new(start, [config(1_000, 500); 5]);
+    assert_eq!(scheduler.poll(start), Some(TaskId::Sensors));
+    scheduler.finish(start + Duration::from_millis(25));
+
+    let stats = scheduler.stats(TaskId::Sensors);
+    assert_eq!(stats.min, Duration::from_millis(25));
+    assert_eq!(stats.max, Duration::from_millis(25));
+    assert_eq!(stats.average(), Duration::from_millis(25));
+}
+
+#[test]
+fn overruns_are_counted() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+
+    let time = start + Duration::from_secs(1);
+    assert!(!run_control(&mut scheduler, time, 500).1);
+    let time = time + Duration::from_secs(1);
+    assert!(run_control(&mut scheduler, time, 501).1);
+    let (end, overrun) = run_control(&mut scheduler, time + Duration::from_secs(1), 900);
+    assert!(overrun);
+
+    let stats = scheduler.stats(TaskId::Control);
+    assert_eq!(stats.overruns, 2);
+    assert_eq!(stats.max, Duration::from_millis(900));
+    assert_eq!(scheduler.stats(TaskId::Sensors).overruns, 0);
+
+    // Without a running task nothing is counted
+    assert!(!scheduler.finish(end));
+    assert_eq!(scheduler.stats(TaskId::Control).overruns, 2);
+}
+
+#[test]
+fn sum_is_halved_before_it_overflows() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+
+    // Four runs fill the sum of the run times almost completely, the fifth halves it. The other
+    // tasks get due again in the meantime
+    let mut time = start;
+    let mut runs = 0;
+    while runs < 5 {
+        time = time + Duration::from_secs(1);
+        while let Some(id) = scheduler.poll(time) {
+            if id != TaskId::Control {
+                scheduler.finish(time);
+                continue;
+            }
+            time = time + Duration::from_millis(1_000_000_000);
+            scheduler.finish(time);
+            runs += 1;
+            break;
+        }
+    }
+    let stats = scheduler.stats(TaskId::Control);
+    assert_eq!(stats.max, Duration::from_millis(1_000_000_000));
+    assert_eq!(stats.average(), Duration::from_millis(1_000_000_000));
+    assert_eq!(stats.overruns, 5);
+}
+
+#[test]
+fn load_of_the_current_window() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    assert_eq!(scheduler.load(start), 0);
+
+    // 100 ms of every second after the first one
+    let time = run_seconds(&mut scheduler, start + Duration::from_secs(1), 30, 100);
+    assert_eq!(scheduler.load(time), 9);
+    let time = run_seconds(&mut scheduler, time, 19, 100);
+    assert_eq!(scheduler.load(time), 9);
+
+    // Nothing runs until the window is complete
+    assert_eq!(scheduler.load(time + Duration::from_secs(10)), 8);
+}
+
+#[test]
+fn load_of_the_last_window_after_the_rollover() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+
+    // The window ends with the run at 60 s, 6 s busy in 60.1 s
+    let time = run_seconds(&mut scheduler, start + Duration::from_secs(1), 60, 100);
+    assert_eq!(scheduler.load(time), 9);
+
+    // The load of the new window is reported once it is complete
+    let time = run_seconds(&mut scheduler, time, 30, 300);
+    assert_eq!(scheduler.load(time), 9);
+    let time = run_seconds(&mut scheduler, time, 30, 300);
+    assert_eq!(scheduler.load(time), 29);
+
+    // Idle window
+    let (end, _) = run_control(&mut scheduler, time + Duration::from_secs(60), 0);
+    assert_eq!(scheduler.load(end), 0);
+}
+
+#[test]
+fn load_is_limited_to_100() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+
+    // A run longer than the time since the start of the window
+    let (end, _) = run_control(&mut scheduler, start + Duration::from_secs(1), 500);
+    assert_eq!(scheduler.load(end - Duration::from_millis(1_200)), 100);
+}
+
+#[test]
+fn reset_restarts_stats_and_window() {
+    let start = Instant::from_millis(0);
+    let mut scheduler = scheduler(start);
+    let time = run_seconds(&mut scheduler, start + Duration::from_secs(1), 70, 600);
+    assert!(scheduler.stats(TaskId::Control).overruns > 0);
+    assert!(scheduler.load(time) > 50);
+
+    scheduler.reset_stats(time);
+    let stats = scheduler.stats(TaskId::Control);
+    assert_eq!(stats.max, Duration::default());
+    assert_eq!(stats.overruns, 0);
+    assert_eq!(stats.average(), Duration::default());
+    assert_eq!(scheduler.load(time), 0);
+
+    let time = run_seconds(&mut scheduler, time, 10, 50);
+    assert_eq!(scheduler.load(time), 5);
+}
@@ -0,0 +1,76 @@
+//! State machine of the firmware, to test the transitions with the events of the main loop
+//!
+//! The legionella schedule runs on a simulated EEPROM. The frost guard takes the readings of
+//! the sensors as they are passed by the control task.
+
+#[macro_use]
+extern crate machine;
+
+#[path = "../../../src/exercise.rs"]
+mod exercise;
+
+#[path = "../../../src/frost.rs"]
+mod frost;
+
+#[path = "../../../src/legionella.rs"]
+mod legionella;
+
+#[path = "../../../src/parameters.rs"]
+mod parameters;
+
+#[path = "../../../src/statemachine.rs"]
+mod statemachine;
+
+mod io {
+    pub use heat_control_io::*;
+}
+
+mod timer {
+    pub use heat_control_io::{Duration, Instant};
+}
+
+pub use exercise::*;
+pub use frost::*;
+pub use heat_control_io::{Duration, Instant};
+pub use legionella::*;
+pub use parameters::*;
+pub use statemachine::*;
+
+/// Simulated EEPROM with the interface of the firmware module
+pub mod eeprom {
+    pub const LEGIONELLA_ADDRESS: u16 = 0x0040;
+
+    const SIZE: usize = 1024;
+
+    /// Checksum of a stored record, kept in the last byte of the record
+    pub fn checksum(data: &[u8]) -> u8 {
+        let mut crc = crc_any::CRCu8::crc8maxim();
+        crc.digest(data);
+        crc.get_crc()
+    }
+
+    /// Erased memory reads as 0xFF like the EEPROM of the controller
+    pub struct Eeprom {
+        data: Vec<u8>,
+    }
+
+    impl Default for Eeprom {
+        fn default() -> Self {
+            Self {
+                data: vec![0xFF; SIZE],
+            }
+        }
+    }
+
+    impl Eeprom {
+        pub fn read(&self, address: u16, buffer: &mut [u8]) {
+            let address = address as usize;
+            buffer.copy_from_slice(&self.data[address..address + buffer.len()]);
+        }
+
+        pub fn write(&mut self, address: u16, data: &[u8]) {
+            let address = address as usize;
+            self.data[address..address + data.len()].copy_from_slice(data);
+        }
+    }
+}
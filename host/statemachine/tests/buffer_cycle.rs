@@ -0,0 +1,292 @@
+//! Buffer cycle of the state machine with the events and outputs of the control task. The buffer
+//! is always warm enough, burner request and heating pump are set by the tests
+
+use heat_control_io::hal::port::Pin;
+use heat_control_io::{
+    OutputId, Outputs, Override, ValveTravel, PUMP_ACTIVE_TIME, PUMP_PAUSE_TIME,
+    VALVE_OPEN_TIMEOUT, VALVE_TRAVEL_TIME,
+};
+use heat_control_statemachine::*;
+
+const CYCLE: Duration = Duration::from_secs(1);
+
+struct Plant {
+    state: HeatControl,
+    outputs: Outputs,
+    time: Instant,
+    start_burner: bool,
+    heating_pump: bool,
+}
+
+impl Plant {
+    fn new(state: HeatControl) -> Self {
+        Self {
+            state,
+            outputs: Outputs::new(Pin::default(), Pin::default(), Pin::default()),
+            time: Instant::from_millis(0),
+            start_burner: false,
+            heating_pump: true,
+        }
+    }
+
+    fn set(&mut self, burner_inhibit: bool, valve: bool, pump: bool) {
+        self.outputs.set_burner_inhibit(burner_inhibit);
+        self.outputs.set_magnet_valve_buffer(valve);
+        self.outputs.set_pump_buffer(pump);
+    }
+
+    /// One cycle of the control task. Returns the outputs delayed by the switch protection
+    fn cycle(&mut self) -> [Option<OutputId>; 3] {
+        let time = self.time;
+        self.state = match std::mem::replace(&mut self.state, HeatControl::Error) {
+            state @ HeatControl::BufferDisabled(_) => {
+                self.set(false, false, false);
+                if !self.start_burner && self.heating_pump {
+                    state.on_enable(Enable {})
+                } else {
+                    state
+                }
+            }
+            state @ HeatControl::ValveOpening(_) => {
+                self.set(true, true, false);
+                if self.heating_pump {
+                    state.on_valve_tick(ValveTick {
+                        time,
+                        open: self.outputs.get_magnet_valve_buffer(),
+                    })
+                } else {
+                    state.on_disable(Disable {})
+                }
+            }
+            state @ HeatControl::BufferEnabled(_) => {
+                self.set(true, true, false);
+                match (self.start_burner, self.heating_pump) {
+                    (_, false) => state.on_disable(Disable {}),
+                    (true, _) => state.on_activate_pump(ActivatePump { time }),
+                    _ => state,
+                }
+            }
+            state @ HeatControl::PumpActive(_) => {
+                self.set(true, true, true);
+                state.on_tick(Tick { time })
+            }
+            state @ HeatControl::PumpPause(_) => {
+                self.set(true, true, false);
+                state.on_tick(Tick { time })
+            }
+            state @ HeatControl::PumpStopping(_) => {
+                self.set(false, true, false);
+                if self.outputs.get_pump_buffer_control() {
+                    state
+                } else {
+                    state.on_pump_stopped(PumpStopped {})
+                }
+            }
+            state => panic!("{} is not part of the buffer cycle", state.to_string()),
+        };
+        let delayed = self.outputs.set_outputs(time);
+        assert!(
+            self.outputs.get_magnet_valve_buffer() || !self.outputs.get_pump_buffer(),
+            "pump runs against the closed valve at {:?}",
+            time
+        );
+        self.time = time + CYCLE;
+        delayed
+    }
+
+    /// Run cycles until the state changes. Returns the time of the last cycle in the old state
+    fn run_state(&mut self) -> Instant {
+        let state = self.state.to_u8();
+        for _ in 0..1_000 {
+            let time = self.time;
+            self.cycle();
+            if self.state.to_u8() != state {
+                return time;
+            }
+        }
+        panic!("{} not left", self.state.to_string());
+    }
+}
+
+fn not_delayed(delayed: [Option<OutputId>; 3]) -> bool {
+    delayed.iter().all(Option::is_none)
+}
+
+#[test]
+fn buffer_cycle() {
+    let mut plant = Plant::new(HeatControl::buffer_disabled());
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Valve Opening");
+
+    // The valve switches in the first cycle of the state, the pump starts after the travel time
+    plant.cycle();
+    let opened = plant.time - CYCLE;
+    assert!(plant.outputs.get_magnet_valve_buffer());
+    let end = plant.run_state();
+    assert!(end - opened > VALVE_TRAVEL_TIME);
+    assert_eq!(plant.state.to_string(), "Buffer Enabled");
+    assert!(!plant.outputs.get_pump_buffer());
+
+    plant.start_burner = true;
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Pump Active");
+    let start = plant.time - CYCLE;
+    let end = plant.run_state();
+    assert!(plant.outputs.get_pump_buffer());
+    assert!(end - start > PUMP_ACTIVE_TIME);
+    assert_eq!(plant.state.to_string(), "Pump Pause");
+
+    let start = end;
+    let end = plant.run_state();
+    assert!(!plant.outputs.get_pump_buffer());
+    assert!(end - start > PUMP_PAUSE_TIME);
+    assert_eq!(plant.state.to_string(), "Buffer Enabled");
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Pump Active");
+}
+
+#[test]
+fn buffer_cycle_is_never_delayed() {
+    let mut plant = Plant::new(HeatControl::buffer_disabled());
+    while plant.state.to_string() != "Buffer Enabled" {
+        plant.cycle();
+    }
+    plant.start_burner = true;
+    let mut starts = 0;
+
+    // Three hours, so the clock window of the starts is reset twice
+    for _ in 0..3 * 60 * 60 {
+        let pump = plant.outputs.get_pump_buffer();
+        assert!(not_delayed(plant.cycle()), "at {:?}", plant.time);
+        if plant.outputs.get_pump_buffer() && !pump {
+            starts += 1;
+        }
+        assert!(plant.outputs.get_burner_inhibit());
+        assert!(plant.outputs.get_magnet_valve_buffer());
+    }
+
+    assert!(starts > 3 * 28, "{} starts", starts);
+    assert_eq!(plant.outputs.get_delayed_count(OutputId::PumpBuffer), 0);
+    assert_eq!(plant.outputs.get_delayed_count(OutputId::BurnerInhibit), 0);
+}
+
+#[test]
+fn pump_stops_before_the_valve_closes() {
+    let mut plant = Plant::new(HeatControl::buffer_disabled());
+    while plant.state.to_string() != "Buffer Enabled" {
+        plant.cycle();
+    }
+    plant.start_burner = true;
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Pump Active");
+
+    // The heating pump stops while the buffer pump runs. The cycle ends with the pause
+    plant.heating_pump = false;
+    plant.run_state();
+    assert_eq!(plant.state.to_string(), "Pump Pause");
+    plant.run_state();
+    assert_eq!(plant.state.to_string(), "Buffer Enabled");
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Pump Stopping");
+
+    // The valve is held open until the pump is off
+    plant.cycle();
+    assert!(plant.outputs.get_magnet_valve_buffer());
+    assert!(!plant.outputs.get_pump_buffer());
+    assert_eq!(plant.state.to_string(), "Buffer Disabled");
+    plant.cycle();
+    assert!(!plant.outputs.get_magnet_valve_buffer());
+}
+
+#[test]
+fn valve_opening_is_disabled() {
+    let mut plant = Plant::new(HeatControl::buffer_disabled());
+    plant.cycle();
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Valve Opening");
+    assert!(plant.outputs.get_magnet_valve_buffer());
+
+    plant.heating_pump = false;
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Pump Stopping");
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Buffer Disabled");
+    assert!(!plant.outputs.get_pump_buffer());
+}
+
+#[test]
+fn valve_opening_gives_up_if_the_valve_is_held_off() {
+    let mut plant = Plant::new(HeatControl::buffer_disabled());
+    plant.outputs.set_override(
+        OutputId::MagnetValveBuffer,
+        Override::ForceOff,
+        plant.time,
+        None,
+    );
+    plant.cycle();
+    let start = plant.time;
+    let end = plant.run_state();
+    assert!(end - start > VALVE_OPEN_TIMEOUT);
+    assert_eq!(plant.state.to_string(), "Pump Stopping");
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Buffer Disabled");
+    assert_eq!(plant.outputs.get_switch_count(OutputId::PumpBuffer), 0);
+}
+
+#[test]
+fn buffer_enabled_is_disabled() {
+    let mut plant = Plant::new(HeatControl::buffer_disabled());
+    while plant.state.to_string() != "Buffer Enabled" {
+        plant.cycle();
+    }
+    plant.heating_pump = false;
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Pump Stopping");
+    plant.cycle();
+    assert_eq!(plant.state.to_string(), "Buffer Disabled");
+}
+
+#[test]
+fn frost_has_priority_over_every_state() {
+    let time = Instant::from_millis(0);
+    let travel = ValveTravel::default();
+    for state in [
+        HeatControl::init(time),
+        HeatControl::buffer_disabled(),
+        HeatControl::valve_opening(travel),
+        HeatControl::buffer_enabled(),
+        HeatControl::pump_active(time),
+        HeatControl::pump_pause(time),
+        HeatControl::buffer_loading(),
+        HeatControl::exercise_valve(travel),
+        HeatControl::exercise_pump(time),
+        HeatControl::legionella(time, None),
+        HeatControl::pump_stopping(),
+    ] {
+        let name = state.to_string();
+        assert_eq!(
+            state.on_frost(Frost {}).to_string(),
+            "Frost Valve",
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn frost_protection_waits_for_the_valve() {
+    let time = Instant::from_millis(0);
+    let state = HeatControl::frost_valve_opening(ValveTravel::default());
+    let state = state.on_valve_tick(ValveTick { time, open: true });
+    assert_eq!(state.to_string(), "Frost Valve");
+    let state = state.on_valve_tick(ValveTick {
+        time: time + VALVE_TRAVEL_TIME + CYCLE,
+        open: true,
+    });
+    assert_eq!(state.to_string(), "Frost Protect");
+    assert_eq!(state.on_thaw(Thaw {}).to_string(), "Pump Stopping");
+
+    // Thaw while the valve opens
+    let state = HeatControl::frost_valve_opening(ValveTravel::default());
+    assert_eq!(state.on_thaw(Thaw {}).to_string(), "Pump Stopping");
+}
@@ -0,0 +1,83 @@
+//! Exercise of valve and pump with the events and outputs of the control task
+
+use heat_control_io::hal::port::Pin;
+use heat_control_io::{OutputId, Outputs, Override, VALVE_OPEN_TIMEOUT, VALVE_TRAVEL_TIME};
+use heat_control_statemachine::*;
+
+const CYCLE: Duration = Duration::from_secs(1);
+
+/// Run the exercise without a burner request until it ends. Returns the states in the order
+/// they were entered and the time of the end
+fn exercise(outputs: &mut Outputs, start: Instant) -> (Vec<&'static str>, Instant) {
+    let mut state = HeatControl::buffer_disabled().on_start_exercise(StartExercise {});
+    let mut states = vec![state.to_string()];
+    let mut time = start;
+    for _ in 0..1_000 {
+        state = match state {
+            state @ HeatControl::ExerciseValve(_) => {
+                outputs.set_magnet_valve_buffer(true);
+                outputs.set_pump_buffer(false);
+                state.on_valve_tick(ValveTick {
+                    time,
+                    open: outputs.get_magnet_valve_buffer(),
+                })
+            }
+            state @ HeatControl::ExercisePump(_) => {
+                outputs.set_magnet_valve_buffer(true);
+                outputs.set_pump_buffer(true);
+                state.on_tick(Tick { time })
+            }
+            state @ HeatControl::PumpStopping(_) => {
+                outputs.set_magnet_valve_buffer(true);
+                outputs.set_pump_buffer(false);
+                if outputs.get_pump_buffer_control() {
+                    state
+                } else {
+                    state.on_pump_stopped(PumpStopped {})
+                }
+            }
+            HeatControl::BufferDisabled(_) => return (states, time),
+            state => panic!("{} is not part of the exercise", state.to_string()),
+        };
+        if *states.last().unwrap() != state.to_string() {
+            states.push(state.to_string());
+        }
+        outputs.set_outputs(time);
+        time = time + CYCLE;
+    }
+    panic!("exercise not finished");
+}
+
+#[test]
+fn valve_and_pump_are_exercised() {
+    let mut outputs = Outputs::new(Pin::default(), Pin::default(), Pin::default());
+    let start = Instant::from_millis(0);
+    let (states, end) = exercise(&mut outputs, start);
+    assert_eq!(
+        states,
+        [
+            "Exercise Valve",
+            "Exercise Pump",
+            "Pump Stopping",
+            "Buffer Disabled"
+        ]
+    );
+    assert!(end - start > VALVE_TRAVEL_TIME + EXERCISE_PUMP_TIME);
+    assert_eq!(outputs.get_switch_count(OutputId::PumpBuffer), 2);
+}
+
+#[test]
+fn exercise_gives_up_if_the_valve_is_held_off() {
+    let mut outputs = Outputs::new(Pin::default(), Pin::default(), Pin::default());
+    let start = Instant::from_millis(0);
+    outputs.set_override(OutputId::MagnetValveBuffer, Override::ForceOff, start, None);
+
+    let (states, end) = exercise(&mut outputs, start);
+    assert_eq!(
+        states,
+        ["Exercise Valve", "Pump Stopping", "Buffer Disabled"]
+    );
+    assert!(end - start > VALVE_OPEN_TIMEOUT);
+    assert!(end - start < VALVE_OPEN_TIMEOUT + VALVE_TRAVEL_TIME);
+    assert_eq!(outputs.get_switch_count(OutputId::PumpBuffer), 0);
+}
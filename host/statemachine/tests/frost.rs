@@ -0,0 +1,77 @@
+//! Frost protection with missing and recovering sensors
+
+use heat_control_statemachine::*;
+
+fn frost_limits() -> (i16, i16) {
+    let parameters = Parameters::default();
+    (
+        parameters.frost_temperature - 1,
+        parameters.frost_temperature + parameters.frost_hysteresis,
+    )
+}
+
+#[test]
+fn frost_is_over_above_the_hysteresis() {
+    let parameters = Parameters::default();
+    let (cold, warm) = frost_limits();
+    let mut guard = FrostGuard::default();
+    assert!(!guard.frost([Some(warm); 4], &parameters));
+    assert!(guard.frost(
+        [Some(warm), Some(cold), Some(warm), Some(warm)],
+        &parameters
+    ));
+
+    // Above the frost temperature, but within the hysteresis
+    let readings = [Some(warm), Some(warm - 1), Some(warm), Some(warm)];
+    assert!(guard.frost(readings, &parameters));
+    assert!(!guard.frost_over(readings, &parameters));
+
+    assert!(guard.frost_over([Some(warm); 4], &parameters));
+    assert!(!guard.frost([Some(warm); 4], &parameters));
+}
+
+#[test]
+fn missing_sensors_keep_the_frost_protection() {
+    let parameters = Parameters::default();
+    let (cold, warm) = frost_limits();
+    let mut guard = FrostGuard::default();
+    assert!(guard.frost(
+        [Some(warm), Some(cold), Some(warm), Some(warm)],
+        &parameters
+    ));
+
+    // The bus fails, so all readings are missing
+    assert!(guard.frost([None; 4], &parameters));
+    assert!(!guard.frost_over([None; 4], &parameters));
+
+    // The sensor that triggered the protection is still missing
+    let readings = [Some(warm), None, Some(warm), Some(warm)];
+    assert!(!guard.frost_over(readings, &parameters));
+
+    assert!(guard.frost_over([Some(warm); 4], &parameters));
+}
+
+#[test]
+fn other_sensors_may_be_missing() {
+    let parameters = Parameters::default();
+    let (cold, warm) = frost_limits();
+    let mut guard = FrostGuard::default();
+    assert!(guard.frost([None, Some(cold), None, None], &parameters));
+    assert!(guard.frost_over([None, Some(warm), None, None], &parameters));
+}
+
+#[test]
+fn all_triggered_sensors_have_to_recover() {
+    let parameters = Parameters::default();
+    let (cold, warm) = frost_limits();
+    let mut guard = FrostGuard::default();
+    assert!(guard.frost(
+        [Some(cold), Some(warm), Some(warm), Some(warm)],
+        &parameters
+    ));
+
+    // The first sensor fails after a second one fell below the frost temperature
+    assert!(guard.frost([None, Some(warm), Some(warm), Some(cold)], &parameters));
+    assert!(!guard.frost_over([Some(warm), Some(warm), Some(warm), None], &parameters));
+    assert!(guard.frost_over([Some(warm); 4], &parameters));
+}
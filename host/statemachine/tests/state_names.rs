@@ -0,0 +1,36 @@
+//! The state names are indexed by the id of the state, so every state needs its own id and name
+
+use heat_control_io::ValveTravel;
+use heat_control_statemachine::*;
+
+#[test]
+fn every_state_has_its_own_name() {
+    let time = Instant::from_millis(0);
+    let travel = ValveTravel::default();
+    let states = [
+        HeatControl::Error,
+        HeatControl::init(time),
+        HeatControl::buffer_disabled(),
+        HeatControl::valve_opening(travel),
+        HeatControl::buffer_enabled(),
+        HeatControl::pump_active(time),
+        HeatControl::pump_pause(time),
+        HeatControl::buffer_loading(),
+        HeatControl::exercise_valve(travel),
+        HeatControl::exercise_pump(time),
+        HeatControl::frost_valve_opening(travel),
+        HeatControl::frost_protection(),
+        HeatControl::legionella(time, None),
+        HeatControl::pump_stopping(),
+    ];
+    assert_eq!(states.len(), STATE_NAMES.len());
+
+    let mut ids: Vec<_> = states.iter().map(HeatControl::to_u8).collect();
+    ids.sort_unstable();
+    assert_eq!(ids, (0..STATE_NAMES.len() as u8).collect::<Vec<_>>());
+
+    let mut names: Vec<_> = states.iter().map(HeatControl::to_string).collect();
+    names.sort_unstable();
+    names.dedup();
+    assert_eq!(names.len(), STATE_NAMES.len());
+}
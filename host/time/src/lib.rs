@@ -0,0 +1,6 @@
+//! Instants and durations of the firmware, to test the arithmetic around the wrap of the timer
+
+#[path = "../../../src/time.rs"]
+mod time;
+
+pub use time::*;
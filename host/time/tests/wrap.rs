@@ -0,0 +1,55 @@
+//! The millisecond counter of the firmware wraps after about 49.7 days
+
+use heat_control_time::{Duration, Instant};
+
+/// Shortly before the counter wraps
+const BEFORE_WRAP: Instant = Instant::from_millis(u32::MAX - 999);
+
+#[test]
+fn duration_across_wrap() {
+    let later = BEFORE_WRAP + Duration::from_secs(2);
+    assert_eq!(later.as_millis(), 1_000);
+    assert_eq!(later - BEFORE_WRAP, Duration::from_secs(2));
+    assert_eq!(later.duration_since(BEFORE_WRAP), Duration::from_secs(2));
+    assert_eq!(later - Duration::from_secs(2), BEFORE_WRAP);
+}
+
+#[test]
+fn has_elapsed_at_deadline() {
+    let deadline = BEFORE_WRAP + Duration::from_secs(5);
+
+    assert!(!BEFORE_WRAP.has_elapsed(deadline - Duration::from_millis(1), Duration::from_secs(5)));
+    assert!(BEFORE_WRAP.has_elapsed(deadline, Duration::from_secs(5)));
+    assert!(BEFORE_WRAP.has_elapsed(deadline + Duration::from_millis(1), Duration::from_secs(5)));
+}
+
+#[test]
+fn has_elapsed_without_wrap() {
+    let start = Instant::from_millis(1_000);
+    assert!(!start.has_elapsed(Instant::from_millis(1_999), Duration::from_secs(1)));
+    assert!(start.has_elapsed(Instant::from_millis(2_000), Duration::from_secs(1)));
+}
+
+#[test]
+fn duration_longer_than_half_the_range() {
+    // About 34.7 days, more than half of the range of the counter
+    let long = Duration::from_secs(3_000_000);
+    assert!(long.as_millis() > u32::MAX / 2);
+
+    let start = Instant::from_millis(u32::MAX / 2);
+    let end = start + long;
+    assert!(end.as_millis() < start.as_millis());
+    assert_eq!(end - start, long);
+    assert!(!start.has_elapsed(end - Duration::from_millis(1), long));
+    assert!(start.has_elapsed(end, long));
+}
+
+#[test]
+fn durations_saturate() {
+    let max = Duration::from_millis(u32::MAX);
+    assert_eq!(max + Duration::from_secs(1), max);
+    assert_eq!(
+        Duration::from_secs(1) + Duration::from_millis(500),
+        Duration::from_millis(1_500)
+    );
+}
@@ -0,0 +1,84 @@
+//! Compact binary telemetry framed with COBS
+//!
+//! Every message is COBS encoded and terminated by a zero byte:
+//!
+//! `<id> <sequence u16> <payload> <crc u16>`
+//!
+//! All numbers are little endian. The CRC is a CRC-16/CCITT-FALSE over id, sequence and payload.
+//!
+//! | id     | payload                                                                     |
+//! |--------|-----------------------------------------------------------------------------|
+//! | `0x01` | temperatures buffer top, buffer bottom, warm water, boiler as i16 in 1/10 °C |
+//! | `0x02` | inputs and outputs as two bit fields, see `INPUT_*` and `OUTPUT_*`          |
+//! | `0x03` | state of the state machine as u8, see `statemachine::STATE_NAMES`           |
+//! | `0x10` | text message: type as ascii character followed by the text of a line frame |
+
+pub const TEMPERATURES: u8 = 0x01;
+pub const IO: u8 = 0x02;
+pub const STATE: u8 = 0x03;
+pub const TEXT: u8 = 0x10;
+
+/// Value of a missing temperature
+pub const MISSING_TEMPERATURE: i16 = i16::MIN;
+
+pub const INPUT_START_BURNER: u8 = 1 << 0;
+pub const INPUT_WARM_WATER_PUMP: u8 = 1 << 1;
+pub const INPUT_HEATING_PUMP: u8 = 1 << 2;
+pub const OUTPUT_BURNER_INHIBIT: u8 = 1 << 0;
+pub const OUTPUT_MAGNET_VALVE_BUFFER: u8 = 1 << 1;
+pub const OUTPUT_PUMP_BUFFER: u8 = 1 << 2;
+
+/// Size of a message including the crc. Longer text is cut. Has to be below 254 so a message is
+/// a single COBS block at most
+const MESSAGE_SIZE: usize = 80;
+
+/// Collects a message until it is encoded
+pub struct Buffer {
+    data: [u8; MESSAGE_SIZE],
+    len: usize,
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Self {
+            data: [0; MESSAGE_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Append data. Returns the part that fitted into the buffer. Space for the crc is kept free
+    pub fn push<'d>(&mut self, data: &'d [u8]) -> &'d [u8] {
+        let len = data.len().min(MESSAGE_SIZE - 2 - self.len);
+        self.data[self.len..self.len + len].copy_from_slice(&data[..len]);
+        self.len += len;
+        &data[..len]
+    }
+
+    /// Append the crc and return the whole message
+    pub fn finish(&mut self, crc: u16) -> &[u8] {
+        let [low, high] = crc.to_le_bytes();
+        self.data[self.len] = low;
+        self.data[self.len + 1] = high;
+        self.len += 2;
+        &self.data[..self.len]
+    }
+}
+
+/// COBS encode a message and pass it byte by byte to `write`, followed by the zero delimiter
+pub fn write_cobs(message: &[u8], mut write: impl FnMut(u8)) {
+    // Each block is the number of bytes up to the next zero plus one, followed by these bytes.
+    // The zero itself is implied by the block
+    for block in message.split(|byte| *byte == 0) {
+        write(block.len() as u8 + 1);
+        for byte in block {
+            write(*byte);
+        }
+    }
+    write(0);
+}
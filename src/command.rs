@@ -0,0 +1,195 @@
+//! Commands received over the serial port
+//!
+//! The bytes of the receiver are collected to lines and parsed to commands in the main loop.
+
+use crate::io::{OutputId, Override};
+use crate::parameters::ParameterId;
+use crate::receiver::Receiver;
+use crate::serial_logger::{Channel, Format};
+use crate::timer::Duration;
+
+const BUFFER_SIZE: usize = 32;
+
+/// Commands that can be send to the controller
+pub enum Command {
+    /// Override an output, until the timeout is over if one is given
+    Force {
+        output: OutputId,
+        mode: Override,
+        timeout: Option<Duration>,
+    },
+    /// Read a parameter or all parameters if none is given
+    Get(Option<ParameterId>),
+    /// Write a parameter
+    Set(ParameterId, i16),
+    /// Report the state of the state machine
+    State,
+    /// Search the one wire bus for sensors
+    Scan,
+    /// Restart the controller by the watchdog
+    Reboot,
+    /// Select the format of the telemetry
+    Telemetry(Format),
+    /// Repeat the Home Assistant discovery
+    Discovery,
+    /// Report the enabled log channels
+    Log,
+    /// Switch a log channel on or off
+    SetLog(Channel, bool),
+    /// Report the execution time statistics of the tasks
+    Stats,
+    /// Restart the execution time statistics
+    ResetStats,
+}
+
+/// Errors while parsing a command
+pub enum Error {
+    /// Command is not known
+    Unknown,
+    /// Missing or invalid argument
+    Argument,
+    /// Line is longer than the receive buffer
+    TooLong,
+}
+
+impl Error {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            Error::Unknown => "ERR Unknown Command",
+            Error::Argument => "ERR Invalid Argument",
+            Error::TooLong => "ERR Line Too Long",
+        }
+    }
+}
+
+/// Collects the received bytes to lines and parses them to commands
+pub struct CommandReader {
+    receiver: Receiver,
+    buffer: [u8; BUFFER_SIZE],
+    len: usize,
+    overflow: bool,
+}
+
+impl CommandReader {
+    pub fn new(receiver: Receiver) -> Self {
+        Self {
+            receiver,
+            buffer: [0; BUFFER_SIZE],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Number of received bytes that were dropped because the ring buffer was full
+    pub fn overflows(&self) -> u16 {
+        self.receiver.overflows()
+    }
+
+    /// Read the received bytes. Returns the parsed command if a line is complete
+    pub fn poll(&mut self) -> Option<Result<Command, Error>> {
+        while let Some(byte) = self.receiver.read() {
+            match byte {
+                b'\r' | b'\n' => {
+                    if self.len == 0 && !self.overflow {
+                        // Ignore empty lines
+                        continue;
+                    }
+                    let result = if self.overflow {
+                        Err(Error::TooLong)
+                    } else {
+                        parse(&self.buffer[..self.len])
+                    };
+                    self.len = 0;
+                    self.overflow = false;
+                    return Some(result);
+                }
+                _ if self.len < BUFFER_SIZE => {
+                    self.buffer[self.len] = byte;
+                    self.len += 1;
+                }
+                _ => self.overflow = true,
+            }
+        }
+        None
+    }
+}
+
+/// Parse a line to a command
+fn parse(line: &[u8]) -> Result<Command, Error> {
+    let line = core::str::from_utf8(line).map_err(|_| Error::Unknown)?;
+    let mut words = line.split_whitespace();
+
+    match words.next() {
+        Some("force") => {
+            let output = match words.next() {
+                Some("burner") => OutputId::BurnerInhibit,
+                Some("valve") => OutputId::MagnetValveBuffer,
+                Some("pump") => OutputId::PumpBuffer,
+                _ => return Err(Error::Argument),
+            };
+            let mode = match words.next() {
+                Some("auto") => Override::Auto,
+                Some("on") => Override::ForceOn,
+                Some("off") => Override::ForceOff,
+                _ => return Err(Error::Argument),
+            };
+            // The timeout is given in s
+            let timeout = match words.next() {
+                Some(timeout) => {
+                    let timeout: u32 = timeout.parse().map_err(|_| Error::Argument)?;
+                    let timeout = timeout.checked_mul(1_000).ok_or(Error::Argument)?;
+                    Some(Duration::from_millis(timeout))
+                }
+                None => None,
+            };
+            Ok(Command::Force {
+                output,
+                mode,
+                timeout,
+            })
+        }
+        Some("get") => match words.next() {
+            None => Ok(Command::Get(None)),
+            Some(name) => ParameterId::from_name(name)
+                .map(|id| Command::Get(Some(id)))
+                .ok_or(Error::Argument),
+        },
+        Some("set") => {
+            let id = words
+                .next()
+                .and_then(ParameterId::from_name)
+                .ok_or(Error::Argument)?;
+            let value = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .ok_or(Error::Argument)?;
+            Ok(Command::Set(id, value))
+        }
+        Some("state") => Ok(Command::State),
+        Some("scan") => Ok(Command::Scan),
+        Some("reboot") => Ok(Command::Reboot),
+        Some("telemetry") => match words.next() {
+            Some("topics") => Ok(Command::Telemetry(Format::Topics)),
+            Some("json") => Ok(Command::Telemetry(Format::Json)),
+            _ => Err(Error::Argument),
+        },
+        Some("discovery") => Ok(Command::Discovery),
+        Some("log") => {
+            let channel = match words.next() {
+                Some(name) => Channel::from_name(name).ok_or(Error::Argument)?,
+                None => return Ok(Command::Log),
+            };
+            match words.next() {
+                Some("on") => Ok(Command::SetLog(channel, true)),
+                Some("off") => Ok(Command::SetLog(channel, false)),
+                _ => Err(Error::Argument),
+            }
+        }
+        Some("stats") => match words.next() {
+            None => Ok(Command::Stats),
+            Some("reset") => Ok(Command::ResetStats),
+            Some(_) => Err(Error::Argument),
+        },
+        _ => Err(Error::Unknown),
+    }
+}
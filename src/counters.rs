@@ -0,0 +1,117 @@
+//! Start and runtime counters of burner and pumps. The counters are persisted in the EEPROM
+
+use crate::eeprom;
+use crate::io;
+use crate::timer::{Duration, Instant};
+
+/// Interval to store the counters in the EEPROM
+const STORE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Size of the counters in the EEPROM including the crc
+const STORE_SIZE: usize = 17;
+
+pub struct Counters {
+    pub burner_starts: u32,
+    /// Runtimes in s
+    pub burner_runtime: u32,
+    pub pump_buffer_runtime: u32,
+    pub heating_pump_runtime: u32,
+    /// Runtimes in ms that are not yet added to the counters
+    burner_ms: u32,
+    pump_buffer_ms: u32,
+    heating_pump_ms: u32,
+    /// Start burner input of the last update
+    start_burner: bool,
+    last_update: Instant,
+    last_store: Instant,
+}
+
+impl Counters {
+    /// Load the counters from the EEPROM. Counters start at zero if the stored data is invalid.
+    /// A burner that is already running at startup is no new start
+    pub fn load(eeprom: &eeprom::Eeprom, inputs: &io::Inputs, time: Instant) -> Self {
+        let mut data = [0_u8; STORE_SIZE];
+        eeprom.read(eeprom::COUNTERS_ADDRESS, &mut data);
+
+        let mut counters = [0_u32; 4];
+        if eeprom::checksum(&data[..STORE_SIZE - 1]) == data[STORE_SIZE - 1] {
+            for (counter, bytes) in counters.iter_mut().zip(data.chunks(4)) {
+                *counter = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+        }
+
+        Self {
+            burner_starts: counters[0],
+            burner_runtime: counters[1],
+            pump_buffer_runtime: counters[2],
+            heating_pump_runtime: counters[3],
+            burner_ms: 0,
+            pump_buffer_ms: 0,
+            heating_pump_ms: 0,
+            start_burner: inputs.get_start_burner(),
+            last_update: time,
+            last_store: time,
+        }
+    }
+
+    /// Count the burner starts and add the runtime since the last update
+    pub fn update(&mut self, time: Instant, inputs: &io::Inputs, outputs: &io::Outputs) {
+        let elapsed = time - self.last_update;
+        self.last_update = time;
+
+        if inputs.get_start_burner() && !self.start_burner {
+            self.burner_starts = self.burner_starts.wrapping_add(1);
+        }
+        self.start_burner = inputs.get_start_burner();
+
+        if inputs.get_start_burner() {
+            add_runtime(&mut self.burner_runtime, &mut self.burner_ms, elapsed);
+        }
+        if outputs.get_pump_buffer() {
+            add_runtime(
+                &mut self.pump_buffer_runtime,
+                &mut self.pump_buffer_ms,
+                elapsed,
+            );
+        }
+        if inputs.get_heating_pump() {
+            add_runtime(
+                &mut self.heating_pump_runtime,
+                &mut self.heating_pump_ms,
+                elapsed,
+            );
+        }
+    }
+
+    /// Store the counters in the EEPROM if the store interval elapsed
+    pub fn store_if_due(&mut self, time: Instant, eeprom: &mut eeprom::Eeprom) {
+        if time - self.last_store >= STORE_INTERVAL {
+            self.store(time, eeprom);
+        }
+    }
+
+    /// Store the counters in the EEPROM, e.g. before a reboot
+    pub fn store(&mut self, time: Instant, eeprom: &mut eeprom::Eeprom) {
+        self.last_store = time;
+
+        let mut data = [0_u8; STORE_SIZE];
+        let counters = [
+            self.burner_starts,
+            self.burner_runtime,
+            self.pump_buffer_runtime,
+            self.heating_pump_runtime,
+        ];
+        for (bytes, counter) in data.chunks_mut(4).zip(counters.iter()) {
+            bytes.copy_from_slice(&counter.to_le_bytes());
+        }
+        data[STORE_SIZE - 1] = eeprom::checksum(&data[..STORE_SIZE - 1]);
+
+        eeprom.write(eeprom::COUNTERS_ADDRESS, &data);
+    }
+}
+
+// Add the elapsed time to the runtime in ms and move full seconds to the counter
+fn add_runtime(counter: &mut u32, ms: &mut u32, elapsed: Duration) {
+    *ms += elapsed.as_millis();
+    *counter = counter.wrapping_add(*ms / 1_000);
+    *ms %= 1_000;
+}
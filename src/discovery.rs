@@ -0,0 +1,98 @@
+//! Home Assistant MQTT discovery of the published values
+//!
+//! The host bridge publishes the configs retained to `homeassistant/<component>/heat_control/...`
+//! and the values below the base topic `heat_control`. Only the telemetry format with one topic
+//! per value is described.
+//!
+//! One config is send per telemetry cycle, as all of them take seconds on the serial port. The
+//! controller is marked as available after the last one.
+
+use crate::serial_logger::SerialLogger;
+
+/// Kind of a published value
+enum Component {
+    /// Temperature in 1/10 °C
+    Temperature,
+    /// `On` or `Off`
+    Binary,
+    /// Plain text
+    Text,
+}
+
+impl Component {
+    fn to_string(&self) -> &'static str {
+        match self {
+            Component::Temperature | Component::Text => "sensor",
+            Component::Binary => "binary_sensor",
+        }
+    }
+
+    /// Additional config entries
+    fn extra(&self) -> &'static str {
+        match self {
+            Component::Temperature => {
+                ",\"dev_cla\":\"temperature\",\"unit_of_meas\":\"°C\",\"val_tpl\":\"{{value|float/10}}\""
+            }
+            Component::Binary => ",\"pl_on\":\"On\",\"pl_off\":\"Off\"",
+            Component::Text => "",
+        }
+    }
+}
+
+/// Values announced to Home Assistant and their topics
+const ENTITIES: [(Component, &str); 11] = [
+    (Component::Temperature, "Temperature/Puffer_Oben"),
+    (Component::Temperature, "Temperature/Puffer_Unten"),
+    (Component::Temperature, "Temperature/Warmwasser"),
+    (Component::Temperature, "Temperature/Kessel"),
+    (Component::Binary, "Inputs/BrennerStart"),
+    (Component::Binary, "Inputs/Pumpe_Warmwasser"),
+    (Component::Binary, "Inputs/Pumpe_Heizung"),
+    (Component::Binary, "Outputs/Brenner_Sperre"),
+    (Component::Binary, "Outputs/Magnetventil_Puffer"),
+    (Component::Binary, "Outputs/Pumpe_Puffer"),
+    (Component::Text, "State"),
+];
+
+/// Announces the values one per cycle
+pub struct Announcer {
+    /// Index of the next entity in `ENTITIES`, the index after the last one marks the controller
+    /// as available. None if the announcement is finished
+    next: Option<usize>,
+}
+
+impl Default for Announcer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Announcer {
+    /// Announce the values, starting with the next cycle
+    pub fn new() -> Self {
+        Self { next: Some(0) }
+    }
+
+    /// Announce all values again. A running announcement starts over
+    pub fn start(&mut self) {
+        self.next = Some(0);
+    }
+
+    /// Send the next part of a running announcement
+    pub fn poll(&mut self, serial: &mut SerialLogger) {
+        let index = match self.next {
+            Some(index) => index,
+            None => return,
+        };
+        match ENTITIES.get(index) {
+            Some((component, topic)) => {
+                serial.announce(component.to_string(), topic, component.extra());
+                self.next = Some(index + 1);
+            }
+            None => {
+                serial.mqtt_str("online", "status");
+                self.next = None;
+            }
+        }
+    }
+}
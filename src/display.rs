@@ -1,60 +1,150 @@
 use crate::hal;
 
-type I2CDisplay = hd44780_driver::HD44780<hd44780_driver::bus::I2CBus<hal::i2c::I2c<super::Clock>>>;
+const DISPLAY_ADD_I2C: u8 = 0x27;
 
+type I2c = hal::i2c::I2c<super::Clock>;
+type I2CDisplay = hd44780_driver::HD44780<hd44780_driver::bus::I2CBus<SharedI2c>>;
+type Delay = hal::delay::Delay<super::Clock>;
+
+/// The I2C bus. Kept outside of the driver, so it is not lost if the initialisation fails or the
+/// display is removed. Only accessed from the main loop
+static mut I2C: Option<I2c> = None;
+
+/// Access of the driver to the bus
+struct SharedI2c;
+
+impl SharedI2c {
+    fn bus(&mut self) -> &mut I2c {
+        unsafe { I2C.as_mut().unwrap() }
+    }
+}
+
+impl embedded_hal::blocking::i2c::Write for SharedI2c {
+    type Error = hal::i2c::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.bus().write(address, bytes)
+    }
+}
+
+enum Bus {
+    /// No display found. Detected again by `retry`
+    Missing,
+    Connected(I2CDisplay),
+}
+
+/// Display on the I2C bus. Without a display all writes are ignored
 pub struct Display {
-    display: I2CDisplay,
-    delay: hal::delay::Delay<super::Clock>,
+    bus: Bus,
+    delay: Delay,
 }
 
 impl Display {
-    pub fn new(mut display: I2CDisplay) -> Self {
-        let mut delay = hal::delay::Delay::<super::Clock>::new();
-        display.clear(&mut delay).ok();
-        display.write_str("Heat Control", &mut delay).ok();
-        Self { display, delay }
+    pub fn new(i2c: I2c) -> Self {
+        unsafe { I2C = Some(i2c) };
+        let mut display = Self {
+            bus: Bus::Missing,
+            delay: Delay::new(),
+        };
+        display.retry();
+        display
+    }
+
+    pub fn connected(&self) -> bool {
+        matches!(self.bus, Bus::Connected(_))
+    }
+
+    /// Try to detect and initialise a missing display. Returns true if the display got connected
+    pub fn retry(&mut self) -> bool {
+        if self.connected() {
+            return false;
+        }
+
+        if !matches!(
+            SharedI2c
+                .bus()
+                .ping_device(DISPLAY_ADD_I2C, hal::i2c::Direction::Write),
+            Ok(true)
+        ) {
+            return false;
+        }
+
+        // If the initialisation fails the display stays missing and is detected again
+        if let Some(display) = Self::init(&mut self.delay) {
+            self.bus = Bus::Connected(display);
+        }
+        self.connected()
+    }
+
+    fn init(delay: &mut Delay) -> Option<I2CDisplay> {
+        let mut display =
+            hd44780_driver::HD44780::new_i2c(SharedI2c, DISPLAY_ADD_I2C, delay).ok()?;
+
+        display.reset(delay).ok()?;
+        display.clear(delay).ok()?;
+        display
+            .set_display_mode(
+                hd44780_driver::DisplayMode {
+                    cursor_blink: hd44780_driver::CursorBlink::Off,
+                    cursor_visibility: hd44780_driver::Cursor::Invisible,
+                    display: hd44780_driver::Display::On,
+                },
+                delay,
+            )
+            .ok()?;
+        display.write_str("Heat Control", delay).ok()?;
+
+        Some(display)
+    }
+
+    /// Write to the display if it is connected. A failed write is handled like a removed display,
+    /// which is detected again by `retry`
+    fn write(
+        &mut self,
+        write: impl FnOnce(&mut I2CDisplay, &mut Delay) -> hd44780_driver::error::Result<()>,
+    ) {
+        if let Bus::Connected(display) = &mut self.bus {
+            if write(display, &mut self.delay).is_err() {
+                self.bus = Bus::Missing;
+            }
+        }
     }
 
     pub fn set_state(&mut self, state: &str) {
-        self.display.set_cursor_pos(0, &mut self.delay).ok();
-        self.display.write_str(state, &mut self.delay).ok();
-        match state.len() {
-            0 => self.display.write_bytes(&[0x20; 16], &mut self.delay).ok(),
-            1 => self.display.write_bytes(&[0x20; 15], &mut self.delay).ok(),
-            2 => self.display.write_bytes(&[0x20; 14], &mut self.delay).ok(),
-            3 => self.display.write_bytes(&[0x20; 13], &mut self.delay).ok(),
-            4 => self.display.write_bytes(&[0x20; 12], &mut self.delay).ok(),
-            5 => self.display.write_bytes(&[0x20; 11], &mut self.delay).ok(),
-            6 => self.display.write_bytes(&[0x20; 10], &mut self.delay).ok(),
-            7 => self.display.write_bytes(&[0x20; 9], &mut self.delay).ok(),
-            8 => self.display.write_bytes(&[0x20; 8], &mut self.delay).ok(),
-            9 => self.display.write_bytes(&[0x20; 7], &mut self.delay).ok(),
-            10 => self.display.write_bytes(&[0x20; 6], &mut self.delay).ok(),
-            11 => self.display.write_bytes(&[0x20; 5], &mut self.delay).ok(),
-            12 => self.display.write_bytes(&[0x20; 4], &mut self.delay).ok(),
-            13 => self.display.write_bytes(&[0x20; 3], &mut self.delay).ok(),
-            14 => self.display.write_bytes(&[0x20; 2], &mut self.delay).ok(),
-            15 => self.display.write_bytes(&[0x20; 1], &mut self.delay).ok(),
-            _ => Some(()),
-        };
+        self.write(|display, delay| {
+            display.set_cursor_pos(0, delay)?;
+            display.write_str(state, delay)?;
+            if state.len() < 16 {
+                display.write_bytes(&[0x20; 16][state.len()..], delay)?;
+            }
+            Ok(())
+        });
+    }
+
+    /// Show a marker in the last column of the first line if an output is in manual mode
+    pub fn set_manual(&mut self, manual: bool) {
+        self.write(|display, delay| {
+            display.set_cursor_pos(0x0F, delay)?;
+            display.write_bytes(if manual { b"H" } else { b" " }, delay)
+        });
     }
 
     pub fn set_temp_top(&mut self, temp: Option<i16>) {
-        self.display.set_cursor_pos(0x40, &mut self.delay).ok();
-        self.display.write_str("O:", &mut self.delay).ok();
-        self.display
-            .write_bytes(&Self::temp_to_bytes(temp), &mut self.delay)
-            .ok();
-        self.display.write_bytes(&[0x20; 2], &mut self.delay).ok();
+        self.write(|display, delay| {
+            display.set_cursor_pos(0x40, delay)?;
+            display.write_str("O:", delay)?;
+            display.write_bytes(&Self::temp_to_bytes(temp), delay)?;
+            display.write_bytes(&[0x20; 2], delay)
+        });
     }
 
     pub fn set_temp_bottom(&mut self, temp: Option<i16>) {
-        self.display.set_cursor_pos(0x48, &mut self.delay).ok();
-        self.display.write_str("U:", &mut self.delay).ok();
-        self.display
-            .write_bytes(&Self::temp_to_bytes(temp), &mut self.delay)
-            .ok();
-        self.display.write_bytes(&[0x20; 2], &mut self.delay).ok();
+        self.write(|display, delay| {
+            display.set_cursor_pos(0x48, delay)?;
+            display.write_str("U:", delay)?;
+            display.write_bytes(&Self::temp_to_bytes(temp), delay)?;
+            display.write_bytes(&[0x20; 2], delay)
+        });
     }
 
     fn temp_to_bytes(temp: Option<i16>) -> [u8; 5] {
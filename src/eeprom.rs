@@ -0,0 +1,73 @@
+//! Access to the internal EEPROM
+
+use crate::chip;
+
+/// Start address of the persisted runtime counters
+pub const COUNTERS_ADDRESS: u16 = 0x0000;
+/// Start address of the reset counters
+pub const RESET_COUNTERS_ADDRESS: u16 = 0x0020;
+/// Address of the stored panic
+pub const PANIC_ADDRESS: u16 = 0x0030;
+/// Address of the enabled log channels
+pub const LOG_CHANNELS_ADDRESS: u16 = 0x0038;
+/// Start address of the legionella schedule
+pub const LEGIONELLA_ADDRESS: u16 = 0x0040;
+
+/// Checksum of a stored record, kept in the last byte of the record
+pub fn checksum(data: &[u8]) -> u8 {
+    let mut crc = crc_any::CRCu8::crc8maxim();
+    crc.digest(data);
+    crc.get_crc()
+}
+
+pub struct Eeprom {
+    eeprom: chip::EEPROM,
+}
+
+#[allow(dead_code)]
+impl Eeprom {
+    pub fn new(eeprom: chip::EEPROM) -> Self {
+        Self { eeprom }
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        self.wait_ready();
+        self.eeprom.eear.write(|w| unsafe { w.bits(address) });
+        self.eeprom.eecr.write(|w| w.eere().set_bit());
+        self.eeprom.eedr.read().bits()
+    }
+
+    /// Write a byte. The byte is only written if the value changed to reduce wear
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        if self.read_byte(address) == value {
+            return;
+        }
+        self.wait_ready();
+        self.eeprom.eear.write(|w| unsafe { w.bits(address) });
+        self.eeprom.eedr.write(|w| unsafe { w.bits(value) });
+        // EEPE has to be set within four cycles after EEMPE
+        avr_device::interrupt::free(|_| {
+            self.eeprom.eecr.write(|w| w.eempe().set_bit());
+            self.eeprom
+                .eecr
+                .write(|w| w.eempe().set_bit().eepe().set_bit());
+        });
+    }
+
+    pub fn read(&self, address: u16, buffer: &mut [u8]) {
+        for (offset, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_byte(address + offset as u16);
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.write_byte(address + offset as u16, *byte);
+        }
+    }
+
+    // Wait until a previous write is finished
+    fn wait_ready(&self) {
+        while self.eeprom.eecr.read().eepe().bit_is_set() {}
+    }
+}
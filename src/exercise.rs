@@ -0,0 +1,39 @@
+//! Monitor the idle time of pump and valve to exercise them before they seize
+
+use crate::io;
+use crate::timer::{Duration, Instant};
+
+/// Exercise pump and valve if one of them was not active for this time
+pub const EXERCISE_IDLE_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+/// Time the pump is running during an exercise
+pub const EXERCISE_PUMP_TIME: Duration = Duration::from_secs(30);
+
+/// Holds the last time the pump and the valve were active
+pub struct IdleMonitor {
+    pump: Instant,
+    valve: Instant,
+}
+
+impl IdleMonitor {
+    pub fn new(time: Instant) -> Self {
+        Self {
+            pump: time,
+            valve: time,
+        }
+    }
+
+    /// Update the last active times with the current output values
+    pub fn update(&mut self, time: Instant, outputs: &io::Outputs) {
+        if outputs.get_pump_buffer() {
+            self.pump = time;
+        }
+        if outputs.get_magnet_valve_buffer() {
+            self.valve = time;
+        }
+    }
+
+    /// Check if pump or valve were idle for too long
+    pub fn exercise_due(&self, time: Instant) -> bool {
+        (time - self.pump > EXERCISE_IDLE_TIME) || (time - self.valve > EXERCISE_IDLE_TIME)
+    }
+}
@@ -0,0 +1,46 @@
+//! Frost protection of the plant
+//!
+//! Frost protection is required as soon as any sensor falls below the frost temperature. It is
+//! released once every sensor that fell below reports a valid temperature above the frost
+//! temperature plus hysteresis again. A sensor that fails during the frost keeps the protection
+//! running, as it can not tell that the frost is over.
+
+use crate::parameters::Parameters;
+
+/// Remembers the sensors that fell below the frost temperature until the frost is over
+#[derive(Default)]
+pub struct FrostGuard {
+    /// Same order as the readings
+    triggered: [bool; 4],
+}
+
+impl FrostGuard {
+    /// Update with the current readings. Returns true while frost protection is required
+    pub fn frost(&mut self, readings: [Option<i16>; 4], parameters: &Parameters) -> bool {
+        for (triggered, reading) in self.triggered.iter_mut().zip(readings) {
+            if matches!(reading, Some(temp) if temp < parameters.frost_temperature) {
+                *triggered = true;
+            }
+        }
+        self.triggered.iter().any(|triggered| *triggered)
+    }
+
+    /// Check if the frost is over. Every sensor that fell below the frost temperature has to
+    /// report a valid temperature above the hysteresis, the other sensors only if they have a
+    /// reading. Releases the frost protection if so
+    pub fn frost_over(&mut self, readings: [Option<i16>; 4], parameters: &Parameters) -> bool {
+        let limit = parameters.frost_temperature + parameters.frost_hysteresis;
+        let over = self
+            .triggered
+            .iter()
+            .zip(readings)
+            .all(|(triggered, reading)| match reading {
+                Some(temp) => temp >= limit,
+                None => !triggered,
+            });
+        if over {
+            self.triggered = [false; 4];
+        }
+        over
+    }
+}
@@ -1,24 +1,232 @@
 //! Definition of Structs for the input and outputs
 use crate::hal;
+use crate::timer::{Duration, Instant};
 
 type OutputPin = hal::port::Pin<hal::port::mode::Output>;
 type InputPin = hal::port::Pin<hal::port::mode::Input<hal::port::mode::Floating>>;
 
+/// Length of the window for counting the starts. The windows follow each other from startup like
+/// a clock, they do not roll. Around the end of a window up to twice the allowed starts can happen
+/// within one hour
+const SWITCH_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Time the pump runs and pauses while the buffer heats the house. Each phase lasts one control
+/// cycle longer, as the state machine switches after the time has passed. The protection of the
+/// pump is sized for this duty cycle of about 29 starts per hour
+pub const PUMP_ACTIVE_TIME: Duration = Duration::from_secs(60);
+pub const PUMP_PAUSE_TIME: Duration = Duration::from_secs(60);
+/// Time the motorised valve needs to open. The pump is started after this time
+pub const VALVE_TRAVEL_TIME: Duration = Duration::from_secs(30);
+/// The valve output has to be switched on within this time after the opening was requested.
+/// Longer than the minimum off time of the valve, so only an override or a valve that ran out of
+/// starts exceeds it
+pub const VALVE_OPEN_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// Protection of the burner against short cycling. Releasing the burner is never delayed
+const BURNER_INHIBIT_PROTECTION: SwitchProtection = SwitchProtection {
+    min_on_time: Duration::from_secs(0),
+    min_off_time: Duration::from_secs(60),
+    max_starts_per_window: 12,
+    safe_state: Some(false),
+};
+const MAGNET_VALVE_BUFFER_PROTECTION: SwitchProtection = SwitchProtection {
+    min_on_time: Duration::from_secs(10),
+    min_off_time: Duration::from_secs(10),
+    max_starts_per_window: 30,
+    safe_state: None,
+};
+/// Stopping the pump is never delayed
+const PUMP_BUFFER_PROTECTION: SwitchProtection = SwitchProtection {
+    min_on_time: Duration::from_secs(0),
+    min_off_time: Duration::from_secs(30),
+    max_starts_per_window: 40,
+    safe_state: Some(false),
+};
+
+/// Limits for switching an output to protect relays and the connected devices
+pub struct SwitchProtection {
+    /// Time the output has to stay on
+    pub min_on_time: Duration,
+    /// Time the output has to stay off
+    pub min_off_time: Duration,
+    /// Switches from off to on within one clock window
+    pub max_starts_per_window: u8,
+    /// State that is switched to without any delay, e.g. the pump off
+    pub safe_state: Option<bool>,
+}
+
+/// Manual override of an output
+#[derive(Copy, Clone, PartialEq)]
+pub enum Override {
+    /// Output is controlled by the control logic
+    Auto,
+    /// Output is forced on
+    ForceOn,
+    /// Output is forced off
+    ForceOff,
+}
+
+/// Identifies an output
+#[derive(Copy, Clone, PartialEq)]
+pub enum OutputId {
+    BurnerInhibit,
+    MagnetValveBuffer,
+    PumpBuffer,
+}
+
+/// All outputs in the order they are set
+pub const OUTPUTS: [OutputId; 3] = [
+    OutputId::BurnerInhibit,
+    OutputId::MagnetValveBuffer,
+    OutputId::PumpBuffer,
+];
+
+impl OutputId {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            OutputId::BurnerInhibit => "Brenner_Sperre",
+            OutputId::MagnetValveBuffer => "Magnetventil_Puffer",
+            OutputId::PumpBuffer => "Pumpe_Puffer",
+        }
+    }
+}
+
+/// A single output with the set value from the control logic and the manual override
+struct Output {
+    pin: OutputPin,
+    /// Set value from the control logic
+    value: bool,
+    /// State of the physical output
+    state: bool,
+    mode: Override,
+    /// Start time and duration of the override. Override is endless if no duration is set
+    override_start: Instant,
+    override_duration: Option<Duration>,
+    protection: SwitchProtection,
+    /// Time of the last switch. None if the output was never switched
+    last_switch: Option<Instant>,
+    window_start: Instant,
+    window_starts: u8,
+    /// A switch request is currently delayed by the protection
+    delayed: bool,
+    switch_count: u32,
+    delayed_count: u32,
+}
+
+impl Output {
+    fn new(pin: OutputPin, protection: SwitchProtection) -> Self {
+        Self {
+            pin,
+            value: false,
+            state: false,
+            mode: Override::Auto,
+            override_start: Instant::from_millis(0),
+            override_duration: None,
+            protection,
+            last_switch: None,
+            window_start: Instant::from_millis(0),
+            window_starts: 0,
+            delayed: false,
+            switch_count: 0,
+            delayed_count: 0,
+        }
+    }
+
+    fn set_override(&mut self, mode: Override, time: Instant, duration: Option<Duration>) {
+        self.mode = mode;
+        self.override_start = time;
+        self.override_duration = duration;
+    }
+
+    /// Set the physical output according to set value and override.
+    /// Returns true if a switch request from the control logic is delayed for the first time
+    fn update(&mut self, time: Instant) -> bool {
+        if let Some(duration) = self.override_duration {
+            if time - self.override_start >= duration {
+                self.set_override(Override::Auto, time, None);
+            }
+        }
+
+        if time - self.window_start >= SWITCH_WINDOW {
+            self.window_start = time;
+            self.window_starts = 0;
+        }
+
+        // Manual overrides are not delayed by the protection
+        let (target, protected) = match self.mode {
+            Override::Auto => (self.value, true),
+            Override::ForceOn => (true, false),
+            Override::ForceOff => (false, false),
+        };
+
+        let mut new_delay = false;
+        if target == self.state {
+            self.delayed = false;
+        } else if protected && !self.switch_allowed(target, time) {
+            new_delay = !self.delayed;
+            if new_delay {
+                self.delayed_count = self.delayed_count.wrapping_add(1);
+            }
+            self.delayed = true;
+        } else {
+            self.state = target;
+            self.delayed = false;
+            self.last_switch = Some(time);
+            if target {
+                self.window_starts = self.window_starts.saturating_add(1);
+            }
+            self.switch_count = self.switch_count.wrapping_add(1);
+        }
+
+        if self.state {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+
+        new_delay
+    }
+
+    /// State of the output as switched by the control logic. While overridden the physical
+    /// state is not controlled by the logic, so the set value is returned instead
+    fn control_state(&self) -> bool {
+        match self.mode {
+            Override::Auto => self.state,
+            Override::ForceOn | Override::ForceOff => self.value,
+        }
+    }
+
+    /// Check minimum on and off time and the starts in the current clock window. Switching to the
+    /// safe state is always allowed
+    fn switch_allowed(&self, target: bool, time: Instant) -> bool {
+        if self.protection.safe_state == Some(target) {
+            return true;
+        }
+        let min_time = if self.state {
+            self.protection.min_on_time
+        } else {
+            self.protection.min_off_time
+        };
+        let time_ok = match self.last_switch {
+            Some(last_switch) => time - last_switch >= min_time,
+            None => true,
+        };
+        time_ok && (!target || self.window_starts < self.protection.max_starts_per_window)
+    }
+}
+
 pub struct Outputs {
-    pump_buffer_pin: OutputPin,
-    pump_buffer: bool,
-    magnet_valve_buffer_pin: OutputPin,
-    magnet_valve_buffer: bool,
-    burner_inhibit_pin: OutputPin,
-    burner_inhibit: bool,
+    pump_buffer: Output,
+    magnet_valve_buffer: Output,
+    burner_inhibit: Output,
 }
 
 /// Impl PartEq only for the logical state
 impl PartialEq for Outputs {
     fn eq(&self, other: &Self) -> bool {
-        (self.pump_buffer == other.pump_buffer)
-            && (self.magnet_valve_buffer == other.magnet_valve_buffer)
-            && (self.burner_inhibit == other.burner_inhibit)
+        (self.pump_buffer.value == other.pump_buffer.value)
+            && (self.magnet_valve_buffer.value == other.magnet_valve_buffer.value)
+            && (self.burner_inhibit.value == other.burner_inhibit.value)
     }
 }
 
@@ -30,68 +238,269 @@ impl Outputs {
         magnet_valve_buffer_pin: OutputPin,
     ) -> Self {
         Self {
-            burner_inhibit: false,
-            burner_inhibit_pin,
-            pump_buffer: false,
-            pump_buffer_pin,
-            magnet_valve_buffer: false,
-            magnet_valve_buffer_pin,
+            burner_inhibit: Output::new(burner_inhibit_pin, BURNER_INHIBIT_PROTECTION),
+            pump_buffer: Output::new(pump_buffer_pin, PUMP_BUFFER_PROTECTION),
+            magnet_valve_buffer: Output::new(
+                magnet_valve_buffer_pin,
+                MAGNET_VALVE_BUFFER_PROTECTION,
+            ),
         }
     }
 
-    /// Set the physical outputs according to the setvalue
-    pub fn set_outputs(&mut self) {
-        if self.burner_inhibit {
-            self.burner_inhibit_pin.set_high();
-        } else {
-            self.burner_inhibit_pin.set_low();
-        }
-
-        if self.magnet_valve_buffer {
-            self.magnet_valve_buffer_pin.set_high();
-        } else {
-            self.magnet_valve_buffer_pin.set_low();
-        }
-
-        if self.pump_buffer {
-            self.pump_buffer_pin.set_high();
-        } else {
-            self.pump_buffer_pin.set_low();
+    /// Set the physical outputs according to the setvalue, the manual overrides and the switch
+    /// protection. Returns the outputs whose switch request got delayed by the protection
+    pub fn set_outputs(&mut self, time: Instant) -> [Option<OutputId>; 3] {
+        let mut delayed = [None; 3];
+        for (delay, output) in delayed.iter_mut().zip(OUTPUTS.iter()) {
+            if self.output_mut(*output).update(time) {
+                *delay = Some(*output);
+            }
         }
+        delayed
     }
 
     pub fn set_magnet_valve_buffer(&mut self, value: bool) {
-        self.magnet_valve_buffer = value
+        self.magnet_valve_buffer.value = value
     }
 
     pub fn set_burner_inhibit(&mut self, value: bool) {
-        self.burner_inhibit = value;
+        self.burner_inhibit.value = value;
     }
 
     pub fn set_pump_buffer(&mut self, value: bool) {
-        self.pump_buffer = value
+        self.pump_buffer.value = value
     }
 
+    /// Physical state of the output including the manual override
     pub fn get_magnet_valve_buffer(&self) -> bool {
-        self.magnet_valve_buffer
+        self.magnet_valve_buffer.state
     }
 
+    /// Physical state of the output including the manual override
     pub fn get_burner_inhibit(&self) -> bool {
-        self.burner_inhibit
+        self.burner_inhibit.state
     }
 
+    /// Physical state of the output including the manual override
     pub fn get_pump_buffer(&self) -> bool {
-        self.pump_buffer
+        self.pump_buffer.state
+    }
+
+    /// State of the pump as switched by the control logic, ignoring a manual override
+    pub fn get_pump_buffer_control(&self) -> bool {
+        self.pump_buffer.control_state()
+    }
+
+    /// Override an output. The override is reset to auto after the duration
+    pub fn set_override(
+        &mut self,
+        output: OutputId,
+        mode: Override,
+        time: Instant,
+        duration: Option<Duration>,
+    ) {
+        self.output_mut(output).set_override(mode, time, duration);
+    }
+
+    pub fn get_override(&self, output: OutputId) -> Override {
+        self.output(output).mode
+    }
+
+    /// Number of switches of the physical output since startup
+    pub fn get_switch_count(&self, output: OutputId) -> u32 {
+        self.output(output).switch_count
+    }
+
+    /// Number of switch requests delayed by the switch protection since startup
+    pub fn get_delayed_count(&self, output: OutputId) -> u32 {
+        self.output(output).delayed_count
+    }
+
+    /// Check if any output is in manual mode
+    pub fn manual_active(&self) -> bool {
+        (self.burner_inhibit.mode != Override::Auto)
+            || (self.magnet_valve_buffer.mode != Override::Auto)
+            || (self.pump_buffer.mode != Override::Auto)
+    }
+
+    fn output(&self, output: OutputId) -> &Output {
+        match output {
+            OutputId::BurnerInhibit => &self.burner_inhibit,
+            OutputId::MagnetValveBuffer => &self.magnet_valve_buffer,
+            OutputId::PumpBuffer => &self.pump_buffer,
+        }
+    }
+
+    fn output_mut(&mut self, output: OutputId) -> &mut Output {
+        match output {
+            OutputId::BurnerInhibit => &mut self.burner_inhibit,
+            OutputId::MagnetValveBuffer => &mut self.magnet_valve_buffer,
+            OutputId::PumpBuffer => &mut self.pump_buffer,
+        }
+    }
+}
+
+/// Progress of the valve travel
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Travel {
+    /// The valve output is not switched on yet or the valve is still moving
+    Moving,
+    /// The valve is fully open
+    Open,
+    /// The valve output was not switched on within the timeout
+    Timeout,
+}
+
+/// Travel of the motorised valve. The travel time is counted from the moment the valve output is
+/// switched on, which the switch protection can delay or refuse
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ValveTravel {
+    /// Time of the first update, when the opening was requested
+    requested: Option<Instant>,
+    /// Time the valve output was switched on. None while it is off
+    open_since: Option<Instant>,
+}
+
+impl ValveTravel {
+    /// Update with the physical state of the valve output. The travel starts again if the output
+    /// is off. The timeout is counted from the first update
+    pub fn update(&mut self, open: bool, time: Instant) -> Travel {
+        let requested = *self.requested.get_or_insert(time);
+        if !open {
+            self.open_since = None;
+            return if time - requested > VALVE_OPEN_TIMEOUT {
+                Travel::Timeout
+            } else {
+                Travel::Moving
+            };
+        }
+        let open_since = *self.open_since.get_or_insert(time);
+        if time - open_since > VALVE_TRAVEL_TIME {
+            Travel::Open
+        } else {
+            Travel::Moving
+        }
+    }
+}
+
+/// Debounce configuration of an input
+#[derive(Copy, Clone)]
+pub enum Debounce {
+    /// Raw value has to be stable for the number of consecutive samples
+    Samples(u8),
+    /// Raw value has to be stable for the time
+    Time(Duration),
+}
+
+/// Contact bounce of the burner start signal is filtered by samples of the control cycle
+const START_BURNER_DEBOUNCE: Debounce = Debounce::Samples(2);
+const WARM_WATER_PUMP_DEBOUNCE: Debounce = Debounce::Samples(2);
+/// The heating pump enables the buffer. Short runs of the pump, e.g. while the heating controller
+/// starts up, are filtered by time
+const HEATING_PUMP_DEBOUNCE: Debounce = Debounce::Time(Duration::from_secs(5));
+
+/// Identifies an input
+#[derive(Copy, Clone, PartialEq)]
+pub enum InputId {
+    StartBurner,
+    WarmWaterPump,
+    HeatingPump,
+}
+
+impl InputId {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            InputId::StartBurner => "BrennerStart",
+            InputId::WarmWaterPump => "Pumpe_Warmwasser",
+            InputId::HeatingPump => "Pumpe_Heizung",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum EdgeKind {
+    Rising,
+    Falling,
+}
+
+impl EdgeKind {
+    /// Topic prefix for the MQTT event of the edge
+    pub fn topic(&self) -> &'static str {
+        match self {
+            EdgeKind::Rising => "Event/Steigend",
+            EdgeKind::Falling => "Event/Fallend",
+        }
+    }
+}
+
+/// Change of a debounced input
+#[derive(Copy, Clone)]
+pub struct Edge {
+    pub input: InputId,
+    pub kind: EdgeKind,
+    /// Time the debounced value changed
+    pub time: Instant,
+}
+
+/// Filters glitches of a raw input value
+struct Debouncer {
+    config: Debounce,
+    /// Last raw value
+    raw: bool,
+    /// Number of consecutive samples with the same raw value
+    samples: u8,
+    /// Time of the last change of the raw value
+    raw_since: Instant,
+    value: bool,
+}
+
+impl Debouncer {
+    /// Start with the first raw sample as stable value
+    fn new(config: Debounce, raw: bool) -> Self {
+        Self {
+            config,
+            raw,
+            samples: 0,
+            raw_since: Instant::from_millis(0),
+            value: raw,
+        }
+    }
+
+    /// Add a raw sample and return the debounced value
+    fn update(&mut self, raw: bool, time: Instant) -> bool {
+        if raw != self.raw {
+            self.raw = raw;
+            self.samples = 0;
+            self.raw_since = time;
+        }
+        self.samples = self.samples.saturating_add(1);
+
+        let stable = match self.config {
+            Debounce::Samples(samples) => self.samples >= samples,
+            Debounce::Time(time_stable) => time - self.raw_since >= time_stable,
+        };
+        if stable {
+            self.value = self.raw;
+        }
+        self.value
     }
 }
 
 pub struct Inputs {
-    pub start_burner: bool,
+    start_burner: bool,
+    /// Not sampled in the simulation
+    #[cfg_attr(feature = "simulation", allow(dead_code))]
     start_burner_pin: InputPin,
+    start_burner_debounce: Debouncer,
+    /// Raw sample of the burner start that replaces the pin in the simulation
+    #[cfg(feature = "simulation")]
+    simulated_start_burner: bool,
     warm_water_pump: bool,
     warm_water_pump_pin: InputPin,
+    warm_water_pump_debounce: Debouncer,
     heating_pump: bool,
     heating_pump_pin: InputPin,
+    heating_pump_debounce: Debouncer,
 }
 
 #[allow(dead_code)]
@@ -101,20 +510,62 @@ impl Inputs {
         warm_water_pump_pin: InputPin,
         heating_pump_pin: InputPin,
     ) -> Self {
+        // The inputs are sampled once, so a signal that is already active at startup is no edge
+        let start_burner = start_burner_pin.is_high();
+        let warm_water_pump = warm_water_pump_pin.is_high();
+        let heating_pump = heating_pump_pin.is_high();
         Self {
-            start_burner: false,
+            start_burner,
             start_burner_pin,
-            warm_water_pump: false,
+            start_burner_debounce: Debouncer::new(START_BURNER_DEBOUNCE, start_burner),
+            #[cfg(feature = "simulation")]
+            simulated_start_burner: start_burner,
+            warm_water_pump,
             warm_water_pump_pin,
-            heating_pump: false,
+            warm_water_pump_debounce: Debouncer::new(WARM_WATER_PUMP_DEBOUNCE, warm_water_pump),
+            heating_pump,
             heating_pump_pin,
+            heating_pump_debounce: Debouncer::new(HEATING_PUMP_DEBOUNCE, heating_pump),
         }
     }
 
-    pub fn get_inputs(&mut self) {
-        self.start_burner = self.start_burner_pin.is_high();
-        self.warm_water_pump = self.warm_water_pump_pin.is_high();
-        self.heating_pump = self.heating_pump_pin.is_high();
+    /// Sample and debounce the inputs. Returns the edges of the debounced values
+    pub fn get_inputs(&mut self, time: Instant) -> [Option<Edge>; 3] {
+        #[cfg(not(feature = "simulation"))]
+        let start_burner_raw = self.start_burner_pin.is_high();
+        #[cfg(feature = "simulation")]
+        let start_burner_raw = self.simulated_start_burner;
+        let start_burner = self.start_burner_debounce.update(start_burner_raw, time);
+        let warm_water_pump = self
+            .warm_water_pump_debounce
+            .update(self.warm_water_pump_pin.is_high(), time);
+        let heating_pump = self
+            .heating_pump_debounce
+            .update(self.heating_pump_pin.is_high(), time);
+
+        let edges = [
+            edge(InputId::StartBurner, self.start_burner, start_burner, time),
+            edge(
+                InputId::WarmWaterPump,
+                self.warm_water_pump,
+                warm_water_pump,
+                time,
+            ),
+            edge(InputId::HeatingPump, self.heating_pump, heating_pump, time),
+        ];
+
+        self.start_burner = start_burner;
+        self.warm_water_pump = warm_water_pump;
+        self.heating_pump = heating_pump;
+
+        edges
+    }
+
+    /// Set the raw sample of the burner start, that is debounced instead of the pin from the next
+    /// call of `get_inputs`
+    #[cfg(feature = "simulation")]
+    pub fn simulate_start_burner(&mut self, raw: bool) {
+        self.simulated_start_burner = raw;
     }
 
     pub fn get_start_burner(&self) -> bool {
@@ -130,6 +581,16 @@ impl Inputs {
     }
 }
 
+/// Detect an edge between the old and the new value
+fn edge(input: InputId, old: bool, new: bool, time: Instant) -> Option<Edge> {
+    let kind = match (old, new) {
+        (false, true) => EdgeKind::Rising,
+        (true, false) => EdgeKind::Falling,
+        _ => return None,
+    };
+    Some(Edge { input, kind, time })
+}
+
 impl PartialEq for Inputs {
     fn eq(&self, other: &Self) -> bool {
         (self.start_burner == other.start_burner)
@@ -0,0 +1,126 @@
+//! Schedule for the thermal disinfection of the warm water tank
+//!
+//! During the cycle the burner is released so the boiler heats the warm water tank. The warm
+//! water setpoint of the boiler has to be above the legionella temperature. Temperature and hold
+//! time are parameters.
+//!
+//! The time since the last start and the last result are persisted in the EEPROM, so a reset does
+//! not postpone the cycle. The time is stored hourly and while the controller is switched off the
+//! time does not advance.
+
+use crate::eeprom;
+use crate::timer::{Duration, Instant};
+
+/// Time between two cycles
+pub const LEGIONELLA_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// The cycle failed if the temperature was not held within this time
+pub const LEGIONELLA_TIMEOUT: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// Interval to store the time since the last start in the EEPROM
+const STORE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Size of the schedule in the EEPROM including the crc
+const STORE_SIZE: usize = 6;
+
+/// Result of a legionella cycle
+#[derive(Copy, Clone, PartialEq)]
+pub enum CycleResult {
+    /// No cycle was finished yet
+    None,
+    Success,
+    Failed,
+}
+
+impl CycleResult {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            CycleResult::None => "None",
+            CycleResult::Success => "Success",
+            CycleResult::Failed => "Failed",
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            CycleResult::None => 0,
+            CycleResult::Success => 1,
+            CycleResult::Failed => 2,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => CycleResult::Success,
+            2 => CycleResult::Failed,
+            _ => CycleResult::None,
+        }
+    }
+}
+
+/// Keeps track when the next cycle is due and the result of the last cycle
+pub struct Schedule {
+    last_start: Instant,
+    last_result: CycleResult,
+    last_store: Instant,
+}
+
+impl Schedule {
+    /// Load the schedule from the EEPROM. Without valid data the first cycle starts after the
+    /// interval
+    pub fn load(eeprom: &eeprom::Eeprom, time: Instant) -> Self {
+        let mut data = [0_u8; STORE_SIZE];
+        eeprom.read(eeprom::LEGIONELLA_ADDRESS, &mut data);
+
+        let (since_start, last_result) =
+            if eeprom::checksum(&data[..STORE_SIZE - 1]) == data[STORE_SIZE - 1] {
+                let secs = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                let secs = secs.min(LEGIONELLA_INTERVAL.as_millis() / 1_000);
+                (Duration::from_secs(secs), CycleResult::from_u8(data[4]))
+            } else {
+                (Duration::from_secs(0), CycleResult::None)
+            };
+
+        Self {
+            last_start: time - since_start,
+            last_result,
+            last_store: time,
+        }
+    }
+
+    /// Check if the next cycle is due
+    pub fn is_due(&self, time: Instant) -> bool {
+        time - self.last_start >= LEGIONELLA_INTERVAL
+    }
+
+    pub fn start(&mut self, time: Instant, eeprom: &mut eeprom::Eeprom) {
+        self.last_start = time;
+        self.store(time, eeprom);
+    }
+
+    pub fn finish(&mut self, result: CycleResult, time: Instant, eeprom: &mut eeprom::Eeprom) {
+        self.last_result = result;
+        self.store(time, eeprom);
+    }
+
+    pub fn last_result(&self) -> CycleResult {
+        self.last_result
+    }
+
+    /// Store the time since the last start if the store interval elapsed
+    pub fn store_if_due(&mut self, time: Instant, eeprom: &mut eeprom::Eeprom) {
+        if time - self.last_store >= STORE_INTERVAL {
+            self.store(time, eeprom);
+        }
+    }
+
+    fn store(&mut self, time: Instant, eeprom: &mut eeprom::Eeprom) {
+        self.last_store = time;
+
+        let mut data = [0_u8; STORE_SIZE];
+        let since_start = (time - self.last_start).as_millis() / 1_000;
+        data[..4].copy_from_slice(&since_start.to_le_bytes());
+        data[4] = self.last_result.to_u8();
+        data[STORE_SIZE - 1] = eeprom::checksum(&data[..STORE_SIZE - 1]);
+
+        eeprom.write(eeprom::LEGIONELLA_ADDRESS, &data);
+    }
+}
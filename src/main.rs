@@ -2,33 +2,60 @@
 #![no_main]
 #![feature(abi_avr_interrupt)]
 
-// Pull in the panic handler from panic-halt
-extern crate avr_std_stub;
-
 #[macro_use]
 extern crate machine;
 
 use atmega_hal as hal;
 use atmega_hal::pac as chip;
-use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayUs;
+use timer::Duration;
 
 type Clock = hal::clock::MHz16;
 
+#[cfg(feature = "binary")]
+mod binary;
+#[cfg(not(feature = "modbus"))]
+mod command;
+mod counters;
+mod discovery;
 mod display;
+mod eeprom;
+mod exercise;
+mod frost;
 mod io;
+mod legionella;
+#[cfg(feature = "modbus")]
+mod modbus;
+#[cfg(feature = "modbus")]
+mod modbus_port;
 mod onewire;
+mod panic;
+mod parameters;
+mod receiver;
+mod report;
+mod reset;
+mod scheduler;
 mod serial_logger;
 mod statemachine;
 mod temperature;
+mod time;
 mod timer;
+mod transmitter;
 
-const DISPLAY_ADD_I2C: u8 = 0x27;
-
-const MIN_CYCLE_TIME: u32 = 1_000;
+const SENSOR_UPDATE_TIME: Duration = Duration::from_secs(1);
+const CONTROL_UPDATE_TIME: Duration = Duration::from_secs(1);
 const WATCHDOG_TIME: hal::wdt::Timeout = hal::wdt::Timeout::Ms4000;
-const DISPLAY_UPDATE_TIME: u32 = 10_000;
-const MQTT_UPDATE_TIME: u32 = 15_000;
-const SERIAL_UPDATE_TIME: u32 = 10_000;
+const DISPLAY_UPDATE_TIME: Duration = Duration::from_secs(10);
+/// Changed values are published within this time
+const MQTT_UPDATE_TIME: Duration = Duration::from_secs(1);
+/// All values are published again after this time
+const MQTT_KEEP_ALIVE_TIME: Duration = Duration::from_secs(300);
+const SERIAL_UPDATE_TIME: Duration = Duration::from_secs(10);
+
+/// Protocol on the receive line of the serial port
+#[cfg(not(feature = "modbus"))]
+type SerialInput = command::CommandReader;
+#[cfg(feature = "modbus")]
+type SerialInput = modbus_port::ModbusPort<receiver::Receiver>;
 
 #[derive(PartialEq, Copy, Clone)]
 #[repr(u8)]
@@ -60,12 +87,14 @@ impl State {
 
 fn setup() -> (
     serial_logger::SerialLogger,
+    SerialInput,
     timer::Timer1,
     io::Outputs,
     io::Inputs,
     temperature::Sensors,
     display::Display,
     hal::wdt::Wdt,
+    eeprom::Eeprom,
 ) {
     // Get Peripherals for configuration
     let peripherals = chip::Peripherals::take().unwrap();
@@ -73,6 +102,12 @@ fn setup() -> (
     // Init the Pins
     let pins = hal::pins!(peripherals);
 
+    // ------------------
+    // Reset Cause
+    // ------------------
+    // Read before the watchdog is configured as this clears the watchdog flag
+    let reset_cause = reset::ResetCause::read(&peripherals.CPU.mcusr);
+
     // ------------------
     // Watchdog
     // ------------------
@@ -84,10 +119,20 @@ fn setup() -> (
     // ------------------
     let rx = pins.pd0.into_floating_input();
     let tx = pins.pd1.into_output();
-    let (_, serial) =
+    let (reader, serial) =
         hal::usart::Usart0::<Clock>::new(peripherals.USART0, rx, tx, 9600.into()).split();
 
-    let mut serial = serial_logger::SerialLogger::new(serial, false, false, true);
+    let mut serial =
+        serial_logger::SerialLogger::new(transmitter::Transmitter::new(serial), false, false, true);
+    let commands = SerialInput::new(receiver::Receiver::new(reader));
+
+    // The log channels can be changed at runtime and are stored in the EEPROM
+    let mut eeprom = eeprom::Eeprom::new(peripherals.EEPROM);
+    serial.load_channels(&eeprom);
+
+    // Messages would disturb the Modbus master
+    #[cfg(feature = "modbus")]
+    serial.mute();
 
     serial.info_str("Heat Control Init");
 
@@ -117,27 +162,14 @@ fn setup() -> (
     let sda = pins.pc4.into_pull_up_input();
     let scl = pins.pc5.into_pull_up_input();
 
-    // Delay for display
-    let mut delay = hal::delay::Delay::<Clock>::new();
     // Create the i2c bus
     let i2c = hal::i2c::I2c::<Clock>::new(peripherals.TWI, sda, scl, 400_000);
-    // Create the display
-    let mut display = hd44780_driver::HD44780::new_i2c(i2c, DISPLAY_ADD_I2C, &mut delay).unwrap();
-
-    display.reset(&mut delay).unwrap();
-    display.clear(&mut delay).unwrap();
-    display
-        .set_display_mode(
-            hd44780_driver::DisplayMode {
-                cursor_blink: hd44780_driver::CursorBlink::Off,
-                cursor_visibility: hd44780_driver::Cursor::Invisible,
-                display: hd44780_driver::Display::On,
-            },
-            &mut delay,
-        )
-        .unwrap();
-
-    let display = display::Display::new(display);
+    // Run without display if none is connected
+    let display = display::Display::new(i2c);
+    if !display.connected() {
+        serial.warn_str("Display Missing");
+        serial.mqtt_str("Missing", "Display");
+    }
 
     serial.debug_str("Done");
 
@@ -152,6 +184,25 @@ fn setup() -> (
     let temperature_sensors = temperature::Sensors::setup(pd2.downgrade());
     serial.debug_str("Done");
 
+    // ------------------
+    // EEPROM
+    // ------------------
+    let reset_counters = reset::ResetCounters::count(&mut eeprom, reset_cause);
+    serial.info_str(reset_cause.to_string());
+    serial.mqtt_str(reset_cause.to_string(), "Reset/Ursache");
+    for cause in reset::CAUSES.iter() {
+        serial.mqtt_u32_sub(
+            reset_counters.get(*cause) as u32,
+            "Reset/Anzahl",
+            cause.to_string(),
+        );
+    }
+
+    if let Some(line) = panic::take_stored(&mut eeprom) {
+        serial.error_str("Panic before Reset");
+        serial.mqtt_u32(line as u32, "Reset/Panic");
+    }
+
     // ------------------
     // TIMER
     // ------------------
@@ -172,12 +223,14 @@ fn setup() -> (
 
     (
         serial,
+        commands,
         timer1,
         outputs,
         inputs,
         temperature_sensors,
         display,
         watchdog,
+        eeprom,
     )
 }
 
@@ -185,193 +238,696 @@ use core::clone::Clone;
 #[hal::entry]
 fn main() -> ! {
     // Init the hardware
-    let (mut serial, timer1, mut outputs, mut inputs, mut sensors, mut display, mut watchdog) =
-        setup();
+    let (
+        mut serial,
+        mut commands,
+        timer1,
+        mut outputs,
+        mut inputs,
+        mut sensors,
+        mut display,
+        mut watchdog,
+        mut eeprom,
+    ) = setup();
+
+    let mut state = statemachine::HeatControl::init(timer1.now());
+    let mut temp_reading = temperature::PlantTemperatures::default();
+    let mut frost_guard = frost::FrostGuard::default();
+    let mut parameters = parameters::Parameters::default();
+    let mut idle_monitor = exercise::IdleMonitor::new(timer1.now());
+    let mut legionella_schedule = legionella::Schedule::load(&eeprom, timer1.now());
+    let mut counters = counters::Counters::load(&eeprom, &inputs, timer1.now());
+    let mut reporter = report::Reporter::new(
+        MQTT_KEEP_ALIVE_TIME,
+        io::OUTPUTS.len(),
+        scheduler::TASKS.len(),
+    );
+    let mut scheduler = scheduler::Scheduler::new(
+        timer1.now(),
+        [
+            scheduler::TaskConfig {
+                period: SENSOR_UPDATE_TIME,
+                deadline: Duration::from_millis(500),
+            },
+            scheduler::TaskConfig {
+                period: CONTROL_UPDATE_TIME,
+                deadline: Duration::from_millis(500),
+            },
+            scheduler::TaskConfig {
+                period: DISPLAY_UPDATE_TIME,
+                deadline: Duration::from_secs(1),
+            },
+            scheduler::TaskConfig {
+                period: MQTT_UPDATE_TIME,
+                deadline: Duration::from_secs(3),
+            },
+            scheduler::TaskConfig {
+                period: SERIAL_UPDATE_TIME,
+                deadline: Duration::from_secs(3),
+            },
+        ],
+    );
 
-    let mut state = statemachine::HeatControl::init(timer1.millis());
-    let mut time_display = 0;
-    let mut time_mqtt: u32 = 0;
-    let mut time_serial = 0;
-    let mut old_state = state.to_u8();
+    let mut announcer = discovery::Announcer::new();
 
     // Main Loop
     loop {
-        let time = timer1.millis();
-
-        inputs.get_inputs();
-
-        #[cfg(not(feature = "simulation"))]
-        let temp_reading = sensors.read_temperatures().unwrap_or_default();
-
-        #[cfg(feature = "simulation")]
-        let mut temp_reading = sensors.read_temperatures().unwrap_or_default();
-
-        #[cfg(feature = "simulation")]
-        {
-            if time < 5_000 {
-                temp_reading.buffer_top = None;
-                inputs.start_burner = false;
-            } else if time < 20_000 {
-                temp_reading.buffer_top = Some(33);
-                inputs.start_burner = false;
-            } else if time < 40_000 {
-                temp_reading.buffer_top = Some(34);
-                inputs.start_burner = true;
-            } else {
-                temp_reading.buffer_top = Some(32);
-                inputs.start_burner = true;
+        let task = match scheduler.poll(timer1.now()) {
+            Some(task) => task,
+            None => {
+                // Poll for serial commands while no task is due
+                #[cfg(not(feature = "modbus"))]
+                {
+                    match commands.poll() {
+                        Some(Ok(command)) => handle_command(
+                            command,
+                            &state,
+                            &mut parameters,
+                            &mut outputs,
+                            &mut sensors,
+                            &mut scheduler,
+                            &mut reporter,
+                            &mut announcer,
+                            &mut counters,
+                            &mut watchdog,
+                            &mut eeprom,
+                            &mut serial,
+                            &timer1,
+                        ),
+                        Some(Err(error)) => serial.reply_str(error.to_string()),
+                        None => (),
+                    }
+                }
+                #[cfg(feature = "modbus")]
+                {
+                    let mut registers = modbus_port::RegisterMap {
+                        temperatures: [
+                            temp_reading.buffer_top,
+                            temp_reading.buffer_buttom,
+                            temp_reading.warm_water,
+                            temp_reading.boiler,
+                        ],
+                        inputs: &inputs,
+                        outputs: &mut outputs,
+                        parameters: &mut parameters,
+                        state: state.to_u8(),
+                        time: timer1.now(),
+                    };
+                    commands.poll(&mut registers, &mut serial);
+                }
+                continue;
             }
-        }
+        };
 
-        // State Machine
-        {
-            use statemachine::*;
-            use temperature::*;
+        let time = timer1.now();
 
-            let new_state = match state {
-                statemachine::HeatControl::Init(_) => {
-                    outputs.set_burner_inhibit(false);
-                    outputs.set_magnet_valve_buffer(false);
-                    outputs.set_pump_buffer(false);
+        match task {
+            scheduler::TaskId::Sensors => {
+                temp_reading = sensors.read_temperatures().unwrap_or_default();
+            }
 
-                    state.on_tick(Tick { time })
+            scheduler::TaskId::Control => {
+                // The simulated burner start is debounced like the pin
+                #[cfg(feature = "simulation")]
+                {
+                    if time.as_millis() < 5_000 {
+                        temp_reading.buffer_top = None;
+                        inputs.simulate_start_burner(false);
+                    } else if time.as_millis() < 20_000 {
+                        temp_reading.buffer_top = Some(33);
+                        inputs.simulate_start_burner(false);
+                    } else if time.as_millis() < 40_000 {
+                        temp_reading.buffer_top = Some(34);
+                        inputs.simulate_start_burner(true);
+                    } else {
+                        temp_reading.buffer_top = Some(32);
+                        inputs.simulate_start_burner(true);
+                    }
                 }
 
-                statemachine::HeatControl::BufferDisabled(_) => {
-                    outputs.set_burner_inhibit(false);
-                    outputs.set_magnet_valve_buffer(false);
-                    outputs.set_pump_buffer(false);
-
-                    match (
-                        temp_reading.buffer_top,
-                        inputs.get_start_burner(),
-                        inputs.get_heating_pump(),
-                    ) {
-                        (Some(temp), false, true)
-                            if temp >= (MIN_BUFFER_TEMPERATURE + BUFFER_HYSTERESIS) =>
-                        {
-                            state.on_enable(Enable {})
-                        }
-                        (_, _, _) => state,
-                    }
+                for edge in inputs.get_inputs(time).iter().flatten() {
+                    serial.mqtt_u32_sub(
+                        edge.time.as_millis(),
+                        edge.kind.topic(),
+                        edge.input.to_string(),
+                    );
                 }
 
-                statemachine::HeatControl::BufferEnabled(_) => {
-                    outputs.set_burner_inhibit(true);
-                    outputs.set_magnet_valve_buffer(true);
-                    outputs.set_pump_buffer(false);
-
-                    match (
-                        temp_reading.buffer_top,
-                        inputs.get_start_burner(),
-                        inputs.get_heating_pump(),
-                    ) {
-                        (None, _, _) => state.on_disable(Disable {}),
-                        (Some(temp), _, _) if temp < MIN_BUFFER_TEMPERATURE => {
-                            state.on_disable(Disable {})
+                let old_state = state.to_u8();
+
+                // State Machine
+                {
+                    use statemachine::*;
+
+                    // Frost protection has priority over every other state
+                    if frost_guard.frost(temp_reading.readings(), &parameters)
+                        && !matches!(
+                            state,
+                            HeatControl::FrostValveOpening(_) | HeatControl::FrostProtection(_)
+                        )
+                    {
+                        // The alarm is published by the telemetry, which is triggered by the
+                        // change of the state
+                        serial.warn_str("Frost Protection Alarm");
+                        if let HeatControl::Legionella(_) = state {
+                            legionella_schedule.finish(
+                                legionella::CycleResult::Failed,
+                                time,
+                                &mut eeprom,
+                            );
                         }
-                        (_, _, false) => state.on_disable(Disable {}),
-                        (Some(_), true, _) => state.on_activate_pump(ActivatePump { time }),
-                        (_, _, _) => state,
+                        state = state.on_frost(Frost {});
                     }
-                }
 
-                statemachine::HeatControl::PumpActive(_) => {
-                    outputs.set_burner_inhibit(true);
-                    outputs.set_magnet_valve_buffer(true);
-                    outputs.set_pump_buffer(true);
+                    let new_state = match state {
+                        statemachine::HeatControl::Init(_) => {
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(false);
+                            outputs.set_pump_buffer(false);
 
-                    state.on_tick(statemachine::Tick { time })
-                }
+                            state.on_tick(Tick { time })
+                        }
 
-                statemachine::HeatControl::PumpPause(_) => {
-                    outputs.set_burner_inhibit(true);
-                    outputs.set_magnet_valve_buffer(true);
-                    outputs.set_pump_buffer(false);
+                        statemachine::HeatControl::BufferDisabled(_) => {
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(false);
+                            outputs.set_pump_buffer(false);
+
+                            match (
+                                temp_reading.buffer_top,
+                                inputs.get_start_burner(),
+                                inputs.get_heating_pump(),
+                            ) {
+                                _ if legionella_schedule.is_due(time) => {
+                                    legionella_schedule.start(time, &mut eeprom);
+                                    state.on_start_legionella(StartLegionella { time })
+                                }
+                                _ if temp_reading.loading_start(&parameters) => {
+                                    state.on_start_loading(StartLoading {})
+                                }
+                                (Some(temp), false, true)
+                                    if temp
+                                        >= (parameters.min_buffer_temperature
+                                            + parameters.buffer_hysteresis) =>
+                                {
+                                    state.on_enable(Enable {})
+                                }
+                                (_, false, _) if idle_monitor.exercise_due(time) => {
+                                    // Info messages are off by default, the event is always sent
+                                    serial.info_str("Exercise Pump and Valve");
+                                    serial.mqtt_str("Start", "Event/Blockierschutz");
+                                    state.on_start_exercise(StartExercise {})
+                                }
+                                (_, _, _) => state,
+                            }
+                        }
 
-                    state.on_tick(statemachine::Tick { time })
-                }
+                        statemachine::HeatControl::ValveOpening(_) => {
+                            outputs.set_burner_inhibit(true);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(false);
+
+                            match (temp_reading.buffer_top, inputs.get_heating_pump()) {
+                                (None, _) => state.on_disable(Disable {}),
+                                (Some(temp), _) if temp < parameters.min_buffer_temperature => {
+                                    state.on_disable(Disable {})
+                                }
+                                (_, false) => state.on_disable(Disable {}),
+                                (_, _) => valve_tick(state, time, &outputs, &mut serial),
+                            }
+                        }
 
-                _ => {
-                    outputs.set_burner_inhibit(true);
-                    outputs.set_magnet_valve_buffer(true);
-                    outputs.set_pump_buffer(false);
-                    state
-                }
-            };
+                        statemachine::HeatControl::BufferEnabled(_) => {
+                            outputs.set_burner_inhibit(true);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(false);
+
+                            match (
+                                temp_reading.buffer_top,
+                                inputs.get_start_burner(),
+                                inputs.get_heating_pump(),
+                            ) {
+                                // The legionella cycle starts after the valve is closed
+                                _ if legionella_schedule.is_due(time) => {
+                                    state.on_disable(Disable {})
+                                }
+                                (None, _, _) => state.on_disable(Disable {}),
+                                (Some(temp), _, _) if temp < parameters.min_buffer_temperature => {
+                                    state.on_disable(Disable {})
+                                }
+                                (_, _, false) => state.on_disable(Disable {}),
+                                (Some(_), true, _) => state.on_activate_pump(ActivatePump { time }),
+                                (_, _, _) => state,
+                            }
+                        }
 
-            state = new_state;
-        }
+                        statemachine::HeatControl::PumpActive(_) => {
+                            outputs.set_burner_inhibit(true);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(true);
+
+                            state.on_tick(statemachine::Tick { time })
+                        }
 
-        let current_state = state.to_u8();
+                        statemachine::HeatControl::BufferLoading(_) => {
+                            // The loading circuit does not pass the valve, so the pump starts
+                            // and stops without waiting for it
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(false);
+                            outputs.set_pump_buffer(true);
+
+                            if temp_reading.loading_stop(&parameters) {
+                                state.on_stop_loading(StopLoading {})
+                            } else {
+                                state
+                            }
+                        }
 
-        // Set Outputs
-        outputs.set_outputs();
+                        statemachine::HeatControl::ExerciseValve(_) => {
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(false);
+
+                            // Abort the exercise as soon as the burner is requested
+                            if inputs.get_start_burner() {
+                                state.on_disable(Disable {})
+                            } else {
+                                valve_tick(state, time, &outputs, &mut serial)
+                            }
+                        }
 
-        // Handle Display
-        if (time.wrapping_sub(time_display) >= DISPLAY_UPDATE_TIME) || (current_state != old_state)
-        {
-            display.set_state(state.to_string());
-            display.set_temp_top(temp_reading.buffer_top);
-            display.set_temp_bottom(temp_reading.buffer_buttom);
-            time_display = time;
-        }
+                        statemachine::HeatControl::ExercisePump(_) => {
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(true);
 
-        // Handle MQTT Messages
-        if (time.wrapping_sub(time_mqtt) >= MQTT_UPDATE_TIME) || (old_state != current_state) {
-            serial.debug_str(state.to_string());
+                            if inputs.get_start_burner() {
+                                state.on_disable(Disable {})
+                            } else {
+                                state.on_tick(Tick { time })
+                            }
+                        }
+
+                        statemachine::HeatControl::FrostValveOpening(_) => {
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(false);
 
-            serial.mqtt_option_i16(temp_reading.buffer_top, "Temperature/Puffer_Oben");
-            serial.mqtt_option_i16(temp_reading.buffer_buttom, "Temperature/Puffer_Unten");
-            serial.mqtt_option_i16(temp_reading.warm_water, "Temperature/Warmwasser");
-            serial.mqtt_option_i16(temp_reading.boiler, "Temperature/Kessel");
+                            if frost_guard.frost_over(temp_reading.readings(), &parameters) {
+                                state.on_thaw(Thaw {})
+                            } else {
+                                valve_tick(state, time, &outputs, &mut serial)
+                            }
+                        }
 
-            serial.mqtt_bool(inputs.get_start_burner(), "Inputs/BrennerStart");
-            serial.mqtt_bool(inputs.get_warm_water_pump(), "Inputs/Pumpe_Warmwasser");
-            serial.mqtt_bool(inputs.get_heating_pump(), "Inputs/Pumpe_Heizung");
+                        statemachine::HeatControl::FrostProtection(_) => {
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(true);
 
-            serial.mqtt_bool(outputs.get_burner_inhibit(), "Outputs/Brenner_Sperre");
-            serial.mqtt_bool(
-                outputs.get_magnet_valve_buffer(),
-                "Outputs/Magnetventil_Puffer",
-            );
-            serial.mqtt_bool(outputs.get_pump_buffer(), "Outputs/Pumpe_Puffer");
+                            if frost_guard.frost_over(temp_reading.readings(), &parameters) {
+                                state.on_thaw(Thaw {})
+                            } else {
+                                state
+                            }
+                        }
 
-            serial.mqtt_str(state.to_string(), "State");
+                        statemachine::HeatControl::Legionella(ref legionella) => {
+                            // Release the burner to heat the warm water tank
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(false);
+                            outputs.set_pump_buffer(false);
+
+                            let check = CheckLegionella {
+                                time,
+                                temperature: temp_reading.warm_water,
+                                target: parameters.legionella_temperature,
+                                hold_time: parameters.legionella_hold(),
+                            };
+                            if let Some(result) = legionella.evaluate(&check) {
+                                // The result is published by the telemetry, which is triggered
+                                // by the change of the state
+                                legionella_schedule.finish(result, time, &mut eeprom);
+                                serial.info_str("Legionella Cycle Finished");
+                            }
+                            state.on_check_legionella(check)
+                        }
 
-            time_mqtt = time;
-        }
+                        statemachine::HeatControl::PumpStopping(_) => {
+                            // Keep the valve open until the pump is off. A pump forced on by
+                            // an override does not block the state machine
+                            outputs.set_burner_inhibit(false);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(false);
+
+                            if outputs.get_pump_buffer_control() {
+                                state
+                            } else {
+                                state.on_pump_stopped(PumpStopped {})
+                            }
+                        }
 
-        // Handle Serial Connection Debug
-        if (time.wrapping_sub(time_serial) >= SERIAL_UPDATE_TIME) || (current_state != old_state) {
-            serial.debug_str(state.to_string());
+                        statemachine::HeatControl::PumpPause(_) => {
+                            outputs.set_burner_inhibit(true);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(false);
 
-            serial.debug_option_i16(temp_reading.buffer_top, "Buffer Top");
-            serial.debug_option_i16(temp_reading.buffer_buttom, "Buffer Bottom");
-            serial.debug_option_i16(temp_reading.warm_water, "Warmwater");
-            serial.debug_option_i16(temp_reading.boiler, "Boiler");
+                            state.on_tick(statemachine::Tick { time })
+                        }
 
-            serial.debug_bool(inputs.get_start_burner(), "Start Burner");
-            serial.debug_bool(inputs.get_warm_water_pump(), "Warmwater Pump");
-            serial.debug_bool(inputs.get_heating_pump(), "Heating Pump");
+                        _ => {
+                            outputs.set_burner_inhibit(true);
+                            outputs.set_magnet_valve_buffer(true);
+                            outputs.set_pump_buffer(false);
+                            state
+                        }
+                    };
 
-            serial.debug_bool(outputs.get_burner_inhibit(), "Burner Inhibit");
-            serial.debug_bool(outputs.get_magnet_valve_buffer(), "Magnet Valve Buffer");
-            serial.debug_bool(outputs.get_pump_buffer(), "Pump Buffer");
+                    state = new_state;
+                }
 
-            time_serial = time;
+                // Set Outputs
+                for output in outputs.set_outputs(time).iter().flatten() {
+                    serial.info_str("Output Switch Delayed");
+                    serial.mqtt_str(output.to_string(), "Event/Ausgang_Verzoegert");
+                }
+                idle_monitor.update(time, &outputs);
+                counters.update(time, &inputs, &outputs);
+                counters.store_if_due(time, &mut eeprom);
+                legionella_schedule.store_if_due(time, &mut eeprom);
+
+                // Update display, telemetry and debug output immediately on a state change
+                if state.to_u8() != old_state {
+                    scheduler.trigger(scheduler::TaskId::Display);
+                    scheduler.trigger(scheduler::TaskId::Telemetry);
+                    scheduler.trigger(scheduler::TaskId::Debug);
+                }
+
+                // Feed the watchdog
+                watchdog.feed();
+            }
+
+            scheduler::TaskId::Display => {
+                if !display.connected() && display.retry() {
+                    serial.info_str("Display Connected");
+                    serial.mqtt_str("Connected", "Display");
+                }
+                display.set_state(state.to_string());
+                display.set_manual(outputs.manual_active());
+                display.set_temp_top(temp_reading.buffer_top);
+                display.set_temp_bottom(temp_reading.buffer_buttom);
+            }
+
+            scheduler::TaskId::Telemetry => {
+                announcer.poll(&mut serial);
+
+                // Only changed values are published, everything with the keep alive
+                let part = reporter.start(time);
+                let deadband = parameters.temperature_deadband;
+                let temperatures = [
+                    (report::Value::BufferTop, temp_reading.buffer_top),
+                    (report::Value::BufferBottom, temp_reading.buffer_buttom),
+                    (report::Value::WarmWater, temp_reading.warm_water),
+                    (report::Value::Boiler, temp_reading.boiler),
+                ];
+                let io = [
+                    (report::Value::StartBurner, inputs.get_start_burner()),
+                    (report::Value::WarmWaterPump, inputs.get_warm_water_pump()),
+                    (report::Value::HeatingPump, inputs.get_heating_pump()),
+                    (report::Value::BurnerInhibit, outputs.get_burner_inhibit()),
+                    (
+                        report::Value::MagnetValveBuffer,
+                        outputs.get_magnet_valve_buffer(),
+                    ),
+                    (report::Value::PumpBuffer, outputs.get_pump_buffer()),
+                ];
+
+                // The binary format has fixed messages for temperatures, IOs and state
+                #[cfg(feature = "binary")]
+                {
+                    if reporter.temperatures(&temperatures, deadband) {
+                        serial.binary_temperatures(&temp_reading);
+                    }
+                    if reporter.flags(&io) {
+                        serial.binary_io(&inputs, &outputs);
+                    }
+                    if reporter.value(report::Value::State, state.to_u8() as i16) {
+                        serial.binary_state(state.to_u8());
+                    }
+                }
+                #[cfg(not(feature = "binary"))]
+                {
+                    if serial.format() == serial_logger::Format::Json {
+                        if reporter.snapshot(&temperatures, &io, state.to_u8(), deadband) {
+                            serial.json_snapshot(
+                                time.as_millis(),
+                                state.to_string(),
+                                &temp_reading,
+                                &inputs,
+                                &outputs,
+                            );
+                        }
+                    } else {
+                        for (value, temperature) in temperatures.iter() {
+                            if reporter.temperature(*value, *temperature, deadband) {
+                                serial.mqtt_option_i16(*temperature, value.topic());
+                            }
+                        }
+                        for (value, flag) in io.iter() {
+                            if reporter.flag(*value, *flag) {
+                                serial.mqtt_bool(*flag, value.topic());
+                            }
+                        }
+                        if reporter.value(report::Value::State, state.to_u8() as i16) {
+                            serial.mqtt_str(state.to_string(), report::Value::State.topic());
+                        }
+                    }
+                }
+
+                if reporter.flag(report::Value::Display, display.connected()) {
+                    serial.mqtt_str(
+                        if display.connected() {
+                            "Connected"
+                        } else {
+                            "Missing"
+                        },
+                        report::Value::Display.topic(),
+                    );
+                }
+                let legionella = legionella_schedule.last_result();
+                if reporter.value(report::Value::Legionella, legionella as i16) {
+                    serial.mqtt_str(legionella.to_string(), report::Value::Legionella.topic());
+                }
+                let frost = matches!(
+                    state,
+                    statemachine::HeatControl::FrostValveOpening(_)
+                        | statemachine::HeatControl::FrostProtection(_)
+                );
+                if reporter.flag(report::Value::Alarm, frost) {
+                    serial.mqtt_str(
+                        if frost { "Frost" } else { "None" },
+                        report::Value::Alarm.topic(),
+                    );
+                }
+
+                // Counters and diagnostics change slowly and are only published with the keep
+                // alive
+                match part {
+                    Some(report::Part::Counters) => {
+                        serial.mqtt_u32(counters.burner_starts, "Counter/Brenner_Starts");
+                        serial.mqtt_u32(counters.burner_runtime, "Counter/Brenner_Laufzeit");
+                        serial.mqtt_u32(
+                            counters.pump_buffer_runtime,
+                            "Counter/Pumpe_Puffer_Laufzeit",
+                        );
+                        serial.mqtt_u32(
+                            counters.heating_pump_runtime,
+                            "Counter/Pumpe_Heizung_Laufzeit",
+                        );
+                    }
+                    Some(report::Part::Switches(index)) => {
+                        let output = io::OUTPUTS[index];
+                        serial.mqtt_u32_sub(
+                            outputs.get_switch_count(output),
+                            "Counter/Schaltungen",
+                            output.to_string(),
+                        );
+                        serial.mqtt_u32_sub(
+                            outputs.get_delayed_count(output),
+                            "Counter/Verzoegerungen",
+                            output.to_string(),
+                        );
+                    }
+                    Some(report::Part::Task(index)) => {
+                        let id = scheduler::TASKS[index];
+                        let stats = scheduler.stats(id);
+                        serial.mqtt_u32_sub(stats.overruns, "Counter/Ueberlauf", id.to_string());
+                        serial.mqtt_u32_sub(
+                            stats.min.as_millis(),
+                            "Diagnose/Laufzeit_Min",
+                            id.to_string(),
+                        );
+                        serial.mqtt_u32_sub(
+                            stats.average().as_millis(),
+                            "Diagnose/Laufzeit_Mittel",
+                            id.to_string(),
+                        );
+                        serial.mqtt_u32_sub(
+                            stats.max.as_millis(),
+                            "Diagnose/Laufzeit_Max",
+                            id.to_string(),
+                        );
+                    }
+                    Some(report::Part::Diagnostics) => {
+                        serial.mqtt_u32(scheduler.load(time), "Diagnose/Auslastung");
+                        serial.mqtt_u32(commands.overflows() as u32, "Diagnose/Empfang_Ueberlauf");
+                        serial.mqtt_u32(serial.overflows() as u32, "Diagnose/Sende_Ueberlauf");
+                    }
+                    _ => (),
+                }
+            }
+
+            scheduler::TaskId::Debug => {
+                serial.debug_str(state.to_string());
+
+                serial.debug_option_i16(temp_reading.buffer_top, "Buffer Top");
+                serial.debug_option_i16(temp_reading.buffer_buttom, "Buffer Bottom");
+                serial.debug_option_i16(temp_reading.warm_water, "Warmwater");
+                serial.debug_option_i16(temp_reading.boiler, "Boiler");
+
+                serial.debug_bool(inputs.get_start_burner(), "Start Burner");
+                serial.debug_bool(inputs.get_warm_water_pump(), "Warmwater Pump");
+                serial.debug_bool(inputs.get_heating_pump(), "Heating Pump");
+
+                serial.debug_bool(outputs.get_burner_inhibit(), "Burner Inhibit");
+                serial.debug_bool(outputs.get_magnet_valve_buffer(), "Magnet Valve Buffer");
+                serial.debug_bool(outputs.get_pump_buffer(), "Pump Buffer");
+            }
         }
 
-        old_state = state.to_u8();
+        if scheduler.finish(timer1.now()) {
+            serial.warn_str("Task Overrun");
+            serial.mqtt_str(task.to_string(), "Event/Ueberlauf");
+        }
+    }
+}
 
-        // Feed the watchdog
-        watchdog.feed();
+/// Pass the physical state of the valve output to a state that waits for the valve. A valve that
+/// was not switched on in time aborts the state
+fn valve_tick(
+    state: statemachine::HeatControl,
+    time: timer::Instant,
+    outputs: &io::Outputs,
+    serial: &mut serial_logger::SerialLogger,
+) -> statemachine::HeatControl {
+    let state = state.on_valve_tick(statemachine::ValveTick {
+        time,
+        open: outputs.get_magnet_valve_buffer(),
+    });
+    if let statemachine::HeatControl::PumpStopping(_) = state {
+        serial.warn_str("Valve Opening Timeout");
+    }
+    state
+}
 
-        // Get time for calculating to this point. Delay next loop to have a nearly const cycle time
-        let calc_time = timer1.millis().wrapping_sub(time);
-        if calc_time <= MIN_CYCLE_TIME {
-            let delay_time = MIN_CYCLE_TIME - calc_time;
-            hal::delay::Delay::<Clock>::new().delay_us(delay_time as u16 * 1_000);
+/// Execute a command received over the serial port
+#[cfg(not(feature = "modbus"))]
+#[allow(clippy::too_many_arguments)]
+fn handle_command(
+    command: command::Command,
+    state: &statemachine::HeatControl,
+    parameters: &mut parameters::Parameters,
+    outputs: &mut io::Outputs,
+    sensors: &mut temperature::Sensors,
+    scheduler: &mut scheduler::Scheduler,
+    reporter: &mut report::Reporter,
+    announcer: &mut discovery::Announcer,
+    counters: &mut counters::Counters,
+    watchdog: &mut hal::wdt::Wdt,
+    eeprom: &mut eeprom::Eeprom,
+    serial: &mut serial_logger::SerialLogger,
+    timer1: &timer::Timer1,
+) {
+    match command {
+        command::Command::Force {
+            output,
+            mode,
+            timeout,
+        } => {
+            outputs.set_override(output, mode, timer1.now(), timeout);
+            serial.reply_str("OK");
+        }
+        command::Command::Get(Some(id)) => {
+            serial.reply_i16(parameters.get(id), id.to_string());
+        }
+        command::Command::Get(None) => {
+            for id in parameters::PARAMETERS.iter() {
+                serial.reply_i16(parameters.get(*id), id.to_string());
+            }
+        }
+        command::Command::Set(id, value) => match parameters.set(id, value) {
+            Ok(()) => serial.reply_str("OK"),
+            Err(parameters::InvalidValue) => serial.reply_str(command::Error::Argument.to_string()),
+        },
+        command::Command::State => {
+            serial.reply_str(state.to_string());
+            serial.reply_bool(outputs.manual_active(), "Manual");
+        }
+        command::Command::Scan => {
+            let result = sensors.scan();
+            serial.reply_u32(result.devices as u32, "Sensors");
+            for (name, found) in temperature::SENSOR_NAMES.iter().zip(result.found.iter()) {
+                serial.reply_bool(*found, name);
+            }
+        }
+        command::Command::Reboot => {
+            // Counted since the last hourly store
+            counters.store(timer1.now(), eeprom);
+            serial.reply_str("OK");
+            serial.flush();
+            // Let the watchdog reset the controller as soon as possible
+            watchdog.start(hal::wdt::Timeout::Ms16).ok();
+            loop {}
+        }
+        command::Command::Telemetry(format) => {
+            serial.set_format(format);
+            reporter.force_all();
+            serial.reply_str("OK");
+        }
+        command::Command::Discovery => {
+            announcer.start();
+            reporter.force_all();
+            serial.reply_str("OK");
+        }
+        command::Command::Log => {
+            for channel in serial_logger::CHANNELS.iter() {
+                let enabled = serial.channel(*channel);
+                serial.reply_bool(enabled, channel.to_string());
+            }
+        }
+        command::Command::SetLog(channel, enabled) => {
+            serial.set_channel(channel, enabled);
+            serial.store_channels(eeprom);
+            // Values were not published while the channel was off
+            reporter.force_all();
+            serial.reply_str("OK");
+        }
+        command::Command::Stats => {
+            for id in scheduler::TASKS.iter() {
+                let stats = scheduler.stats(*id);
+                serial.reply_timing(
+                    id.to_string(),
+                    stats.min.as_millis(),
+                    stats.average().as_millis(),
+                    stats.max.as_millis(),
+                    stats.overruns,
+                );
+            }
+            serial.reply_u32(scheduler.load(timer1.now()), "Load [%]");
+        }
+        command::Command::ResetStats => {
+            scheduler.reset_stats(timer1.now());
+            serial.reply_str("OK");
         }
     }
 }
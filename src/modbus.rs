@@ -0,0 +1,370 @@
+//! Modbus RTU slave protocol
+//!
+//! A request ends when the line is silent for 3.5 characters and is checked with a
+//! CRC-16/MODBUS. The serial port is in `modbus_port`. Supported functions:
+//!
+//! | code | function                 |
+//! |------|--------------------------|
+//! | 1    | read coils               |
+//! | 2    | read discrete inputs     |
+//! | 3    | read holding registers   |
+//! | 4    | read input registers     |
+//! | 5    | write single coil        |
+//! | 6    | write single register    |
+//! | 15   | write multiple coils     |
+//! | 16   | write multiple registers |
+
+use crc_any::CRCu16;
+
+/// Size of the request and response buffers. Smaller than the 256 bytes of the standard to save
+/// RAM. Longer requests are dropped and reads are limited to fit into a response
+pub const FRAME_SIZE: usize = 64;
+
+/// Silence in ms that ends a frame. 3.5 characters take 3.6 ms at 9600 baud, one more ms is
+/// added for the resolution of the timer
+pub const FRAME_GAP_MS: u32 = 5;
+
+/// Check if the line was silent long enough to end a frame
+pub fn is_frame_gap(silence_ms: u32) -> bool {
+    silence_ms >= FRAME_GAP_MS
+}
+
+/// Address of requests to all slaves. These are executed but not answered
+pub const BROADCAST: u8 = 0;
+
+const READ_COILS: u8 = 1;
+const READ_DISCRETE_INPUTS: u8 = 2;
+const READ_HOLDING_REGISTERS: u8 = 3;
+const READ_INPUT_REGISTERS: u8 = 4;
+const WRITE_SINGLE_COIL: u8 = 5;
+const WRITE_SINGLE_REGISTER: u8 = 6;
+const WRITE_MULTIPLE_COILS: u8 = 15;
+const WRITE_MULTIPLE_REGISTERS: u8 = 16;
+
+/// Set in the function code of a response to report an exception
+const EXCEPTION_FLAG: u8 = 0x80;
+const COIL_ON: u16 = 0xFF00;
+const COIL_OFF: u16 = 0x0000;
+
+/// Exception codes reported to the master
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exception {
+    IllegalFunction = 1,
+    IllegalDataAddress = 2,
+    IllegalDataValue = 3,
+}
+
+/// Data of the slave. Addresses start at 0, unknown addresses are `None`
+pub trait Registers {
+    fn coil(&self, address: u16) -> Option<bool>;
+    fn write_coil(&mut self, address: u16, value: bool) -> Result<(), Exception>;
+    fn discrete_input(&self, address: u16) -> Option<bool>;
+    fn input_register(&self, address: u16) -> Option<u16>;
+    fn holding_register(&self, address: u16) -> Option<u16>;
+    fn write_holding_register(&mut self, address: u16, value: u16) -> Result<(), Exception>;
+    /// Write consecutive holding registers. If a value is rejected none of them is written
+    fn write_holding_registers(&mut self, start: u16, values: &[u16]) -> Result<(), Exception>;
+}
+
+/// Collects the received bytes of a request
+pub struct FrameBuffer {
+    data: [u8; FRAME_SIZE],
+    len: usize,
+    overflow: bool,
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self {
+            data: [0; FRAME_SIZE],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.len < FRAME_SIZE {
+            self.data[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.overflow = true;
+        }
+    }
+
+    /// Take the received frame after its end was detected. Frames that did not fit into the
+    /// buffer are dropped
+    pub fn take(&mut self) -> Option<&[u8]> {
+        if self.len == 0 {
+            return None;
+        }
+        let len = self.len;
+        let overflow = self.overflow;
+        self.len = 0;
+        self.overflow = false;
+        if overflow {
+            None
+        } else {
+            Some(&self.data[..len])
+        }
+    }
+}
+
+pub struct Slave {
+    address: u8,
+}
+
+impl Slave {
+    pub fn new(address: u8) -> Self {
+        Self { address }
+    }
+
+    /// Execute a request. Returns the length of the response or `None` if the request is not
+    /// answered because it is corrupted, for another slave or a broadcast
+    pub fn handle<R: Registers>(
+        &self,
+        request: &[u8],
+        registers: &mut R,
+        response: &mut [u8; FRAME_SIZE],
+    ) -> Option<usize> {
+        if request.len() < 4 {
+            return None;
+        }
+        let (content, received) = request.split_at(request.len() - 2);
+        if crc(content) != u16::from_le_bytes([received[0], received[1]]) {
+            return None;
+        }
+        let address = content[0];
+        if address != self.address && address != BROADCAST {
+            return None;
+        }
+
+        let function = content[1];
+        let data = &content[2..];
+        // Space for address, function and crc is kept free
+        let body = &mut response[2..FRAME_SIZE - 2];
+        let result = match function {
+            READ_COILS => read_bits(data, body, |address| registers.coil(address)),
+            READ_DISCRETE_INPUTS => {
+                read_bits(data, body, |address| registers.discrete_input(address))
+            }
+            READ_HOLDING_REGISTERS => {
+                read_registers(data, body, |address| registers.holding_register(address))
+            }
+            READ_INPUT_REGISTERS => {
+                read_registers(data, body, |address| registers.input_register(address))
+            }
+            WRITE_SINGLE_COIL => write_single_coil(data, body, registers),
+            WRITE_SINGLE_REGISTER => write_single_register(data, body, registers),
+            WRITE_MULTIPLE_COILS => write_multiple_coils(data, body, registers),
+            WRITE_MULTIPLE_REGISTERS => write_multiple_registers(data, body, registers),
+            _ => Err(Exception::IllegalFunction),
+        };
+
+        if address == BROADCAST {
+            return None;
+        }
+
+        response[0] = self.address;
+        let len = match result {
+            Ok(len) => {
+                response[1] = function;
+                2 + len
+            }
+            Err(exception) => {
+                response[1] = function | EXCEPTION_FLAG;
+                response[2] = exception as u8;
+                3
+            }
+        };
+        let [low, high] = crc(&response[..len]).to_le_bytes();
+        response[len] = low;
+        response[len + 1] = high;
+        Some(len + 2)
+    }
+}
+
+/// Checksum of a frame, send low byte first
+pub fn crc(data: &[u8]) -> u16 {
+    let mut crc = CRCu16::crc16modbus();
+    crc.digest(data);
+    crc.get_crc()
+}
+
+fn word(data: &[u8], index: usize) -> u16 {
+    u16::from_be_bytes([data[index], data[index + 1]])
+}
+
+/// Addresses of `quantity` values starting at `start`
+fn addresses(start: u16, quantity: u16) -> Result<core::ops::Range<u16>, Exception> {
+    let end = start
+        .checked_add(quantity)
+        .ok_or(Exception::IllegalDataAddress)?;
+    Ok(start..end)
+}
+
+/// Number of bytes to hold `quantity` bits
+fn bit_bytes(quantity: u16) -> usize {
+    let quantity = quantity as usize;
+    // Eight bits per byte, a started byte counts fully
+    quantity / 8 + (quantity & 0x07 != 0) as usize
+}
+
+fn read_bits(
+    data: &[u8],
+    body: &mut [u8],
+    value: impl Fn(u16) -> Option<bool>,
+) -> Result<usize, Exception> {
+    if data.len() != 4 {
+        return Err(Exception::IllegalDataValue);
+    }
+    let quantity = word(data, 2);
+    let bytes = bit_bytes(quantity);
+    if quantity == 0 || 1 + bytes > body.len() {
+        return Err(Exception::IllegalDataValue);
+    }
+
+    body[0] = bytes as u8;
+    for byte in body[1..=bytes].iter_mut() {
+        *byte = 0;
+    }
+    for (index, address) in addresses(word(data, 0), quantity)?.enumerate() {
+        if value(address).ok_or(Exception::IllegalDataAddress)? {
+            body[1 + index / 8] |= 1 << (index % 8);
+        }
+    }
+    Ok(1 + bytes)
+}
+
+fn read_registers(
+    data: &[u8],
+    body: &mut [u8],
+    value: impl Fn(u16) -> Option<u16>,
+) -> Result<usize, Exception> {
+    if data.len() != 4 {
+        return Err(Exception::IllegalDataValue);
+    }
+    let quantity = word(data, 2);
+    let bytes = 2 * quantity as usize;
+    if quantity == 0 || 1 + bytes > body.len() {
+        return Err(Exception::IllegalDataValue);
+    }
+
+    body[0] = bytes as u8;
+    for (index, address) in addresses(word(data, 0), quantity)?.enumerate() {
+        let [high, low] = value(address)
+            .ok_or(Exception::IllegalDataAddress)?
+            .to_be_bytes();
+        body[1 + 2 * index] = high;
+        body[2 + 2 * index] = low;
+    }
+    Ok(1 + bytes)
+}
+
+fn write_single_coil<R: Registers>(
+    data: &[u8],
+    body: &mut [u8],
+    registers: &mut R,
+) -> Result<usize, Exception> {
+    if data.len() != 4 {
+        return Err(Exception::IllegalDataValue);
+    }
+    let value = match word(data, 2) {
+        COIL_ON => true,
+        COIL_OFF => false,
+        _ => return Err(Exception::IllegalDataValue),
+    };
+    let address = word(data, 0);
+    registers
+        .coil(address)
+        .ok_or(Exception::IllegalDataAddress)?;
+    registers.write_coil(address, value)?;
+
+    // The response repeats the request
+    body[..4].copy_from_slice(data);
+    Ok(4)
+}
+
+fn write_single_register<R: Registers>(
+    data: &[u8],
+    body: &mut [u8],
+    registers: &mut R,
+) -> Result<usize, Exception> {
+    if data.len() != 4 {
+        return Err(Exception::IllegalDataValue);
+    }
+    let address = word(data, 0);
+    registers
+        .holding_register(address)
+        .ok_or(Exception::IllegalDataAddress)?;
+    registers.write_holding_register(address, word(data, 2))?;
+
+    body[..4].copy_from_slice(data);
+    Ok(4)
+}
+
+fn write_multiple_coils<R: Registers>(
+    data: &[u8],
+    body: &mut [u8],
+    registers: &mut R,
+) -> Result<usize, Exception> {
+    if data.len() < 5 {
+        return Err(Exception::IllegalDataValue);
+    }
+    let quantity = word(data, 2);
+    let bytes = data[4] as usize;
+    if quantity == 0 || bytes != bit_bytes(quantity) || data.len() != 5 + bytes {
+        return Err(Exception::IllegalDataValue);
+    }
+
+    // Check all addresses first so a request is either executed completely or not at all
+    let addresses = addresses(word(data, 0), quantity)?;
+    if addresses
+        .clone()
+        .any(|address| registers.coil(address).is_none())
+    {
+        return Err(Exception::IllegalDataAddress);
+    }
+    for (index, address) in addresses.enumerate() {
+        let value = data[5 + index / 8] & (1 << (index % 8)) != 0;
+        registers.write_coil(address, value)?;
+    }
+
+    // The response repeats start and quantity
+    body[..4].copy_from_slice(&data[..4]);
+    Ok(4)
+}
+
+fn write_multiple_registers<R: Registers>(
+    data: &[u8],
+    body: &mut [u8],
+    registers: &mut R,
+) -> Result<usize, Exception> {
+    if data.len() < 5 {
+        return Err(Exception::IllegalDataValue);
+    }
+    let quantity = word(data, 2);
+    let bytes = data[4] as usize;
+    if quantity == 0 || bytes != 2 * quantity as usize || data.len() != 5 + bytes {
+        return Err(Exception::IllegalDataValue);
+    }
+
+    let start = word(data, 0);
+    if addresses(start, quantity)?.any(|address| registers.holding_register(address).is_none()) {
+        return Err(Exception::IllegalDataAddress);
+    }
+    // A request fits into the frame, so it has less values than half its size
+    let mut values = [0; FRAME_SIZE / 2];
+    for (index, value) in values[..quantity as usize].iter_mut().enumerate() {
+        *value = word(data, 5 + 2 * index);
+    }
+    registers.write_holding_registers(start, &values[..quantity as usize])?;
+
+    body[..4].copy_from_slice(&data[..4]);
+    Ok(4)
+}
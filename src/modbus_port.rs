@@ -0,0 +1,226 @@
+//! Modbus RTU slave on the serial port (feature `modbus`)
+//!
+//! The serial port is used for Modbus only, all other messages are muted. The slave address is
+//! `SLAVE_ADDRESS`, all register addresses start at 0:
+//!
+//! | table             | address | value                                                       |
+//! |-------------------|---------|-------------------------------------------------------------|
+//! | input registers   | 0 - 3   | buffer top, buffer bottom, warm water, boiler in 1/10 °C    |
+//! | discrete inputs   | 0 - 2   | start burner, warm water pump, heating pump                 |
+//! | coils             | 0 - 2   | burner inhibit, magnet valve buffer, pump buffer            |
+//! | holding registers | 0       | state of the state machine, read only                       |
+//! | holding registers | 1 - 10  | control parameters in the order of `parameters::PARAMETERS` |
+//! | holding registers | 11 - 13 | override of the outputs: 0 auto, 1 forced on, 2 forced off  |
+//!
+//! Missing temperatures are `0x8000`. Writing a coil forces the output on or off until the
+//! override is set back to auto. The serial port is accessed through `Receive` and `Transmit`, so
+//! the slave runs without the hardware.
+
+use crate::io::{Inputs, OutputId, Outputs, Override, OUTPUTS};
+use crate::modbus::{self, Exception, FrameBuffer, Registers, Slave};
+use crate::parameters::{ParameterId, Parameters, PARAMETERS};
+use crate::timer::{Duration, Instant};
+
+/// Address of the controller on the bus
+pub const SLAVE_ADDRESS: u8 = 1;
+
+/// Value of a missing temperature
+const MISSING_TEMPERATURE: i16 = i16::MIN;
+
+const STATE_REGISTER: u16 = 0;
+/// First register of the parameters
+const PARAMETER_REGISTERS: u16 = 1;
+/// First register of the overrides, follows the parameters
+const OVERRIDE_REGISTERS: u16 = PARAMETER_REGISTERS + PARAMETERS.len() as u16;
+
+/// Receive line of the serial port
+pub trait Receive {
+    /// Take the next received byte and if it starts a frame, i.e. the line was silent for the
+    /// frame gap before it
+    fn read_frame_byte(&mut self) -> Option<(u8, bool)>;
+    /// Time since the last byte was received
+    fn silence(&self) -> Duration;
+    /// Number of received bytes that were dropped
+    fn overflows(&self) -> u16;
+}
+
+/// Transmit line of the serial port
+pub trait Transmit {
+    fn write_raw(&mut self, data: &[u8]);
+}
+
+/// Values of the controller as seen by the master
+pub struct RegisterMap<'a> {
+    /// Buffer top, buffer bottom, warm water and boiler in the order of the input registers
+    pub temperatures: [Option<i16>; 4],
+    pub inputs: &'a Inputs,
+    pub outputs: &'a mut Outputs,
+    pub parameters: &'a mut Parameters,
+    /// Id of the state of the state machine
+    pub state: u8,
+    pub time: Instant,
+}
+
+impl RegisterMap<'_> {
+    fn parameter(address: u16) -> Option<ParameterId> {
+        let index = address.checked_sub(PARAMETER_REGISTERS)?;
+        PARAMETERS.get(index as usize).copied()
+    }
+
+    /// Index of the output in `OUTPUTS`
+    fn override_index(address: u16) -> Option<usize> {
+        let index = address.checked_sub(OVERRIDE_REGISTERS)? as usize;
+        if index < OUTPUTS.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    fn override_mode(value: u16) -> Result<Override, Exception> {
+        match value {
+            0 => Ok(Override::Auto),
+            1 => Ok(Override::ForceOn),
+            2 => Ok(Override::ForceOff),
+            _ => Err(Exception::IllegalDataValue),
+        }
+    }
+}
+
+impl Registers for RegisterMap<'_> {
+    fn coil(&self, address: u16) -> Option<bool> {
+        match OUTPUTS.get(address as usize)? {
+            OutputId::BurnerInhibit => Some(self.outputs.get_burner_inhibit()),
+            OutputId::MagnetValveBuffer => Some(self.outputs.get_magnet_valve_buffer()),
+            OutputId::PumpBuffer => Some(self.outputs.get_pump_buffer()),
+        }
+    }
+
+    fn write_coil(&mut self, address: u16, value: bool) -> Result<(), Exception> {
+        let output = OUTPUTS
+            .get(address as usize)
+            .ok_or(Exception::IllegalDataAddress)?;
+        let mode = if value {
+            Override::ForceOn
+        } else {
+            Override::ForceOff
+        };
+        self.outputs.set_override(*output, mode, self.time, None);
+        Ok(())
+    }
+
+    fn discrete_input(&self, address: u16) -> Option<bool> {
+        match address {
+            0 => Some(self.inputs.get_start_burner()),
+            1 => Some(self.inputs.get_warm_water_pump()),
+            2 => Some(self.inputs.get_heating_pump()),
+            _ => None,
+        }
+    }
+
+    fn input_register(&self, address: u16) -> Option<u16> {
+        let temperature = self.temperatures.get(address as usize)?;
+        Some(temperature.unwrap_or(MISSING_TEMPERATURE) as u16)
+    }
+
+    fn holding_register(&self, address: u16) -> Option<u16> {
+        if address == STATE_REGISTER {
+            return Some(self.state as u16);
+        }
+        if let Some(id) = Self::parameter(address) {
+            return Some(self.parameters.get(id) as u16);
+        }
+        let mode = self
+            .outputs
+            .get_override(OUTPUTS[Self::override_index(address)?]);
+        Some(match mode {
+            Override::Auto => 0,
+            Override::ForceOn => 1,
+            Override::ForceOff => 2,
+        })
+    }
+
+    fn write_holding_register(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+        self.write_holding_registers(address, &[value])
+    }
+
+    fn write_holding_registers(&mut self, start: u16, values: &[u16]) -> Result<(), Exception> {
+        // All values are checked before any of them is applied
+        let addresses = (0..values.len() as u16).map(|index| start.wrapping_add(index));
+        let mut overrides = [None; OUTPUTS.len()];
+        for (address, value) in addresses.clone().zip(values.iter()) {
+            if let Some(index) = Self::override_index(address) {
+                overrides[index] = Some(Self::override_mode(*value)?);
+            } else if Self::parameter(address).is_none() {
+                // The state is read only
+                return Err(Exception::IllegalDataAddress);
+            }
+        }
+        // The parameters are checked together, e.g. loading start and stop difference
+        let mut parameters = *self.parameters;
+        parameters
+            .set_all(
+                addresses.zip(values.iter()).filter_map(|(address, value)| {
+                    Some((Self::parameter(address)?, *value as i16))
+                }),
+            )
+            .map_err(|_| Exception::IllegalDataValue)?;
+
+        *self.parameters = parameters;
+        for (output, mode) in OUTPUTS.iter().zip(overrides.iter()) {
+            if let Some(mode) = mode {
+                self.outputs.set_override(*output, *mode, self.time, None);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects the requests from the receiver and sends the responses
+pub struct ModbusPort<R> {
+    receiver: R,
+    frame: FrameBuffer,
+    slave: Slave,
+}
+
+impl<R: Receive> ModbusPort<R> {
+    pub fn new(receiver: R) -> Self {
+        Self {
+            receiver,
+            frame: FrameBuffer::new(),
+            slave: Slave::new(SLAVE_ADDRESS),
+        }
+    }
+
+    /// Number of received bytes that were dropped because the ring buffer was full
+    pub fn overflows(&self) -> u16 {
+        self.receiver.overflows()
+    }
+
+    /// Read the received bytes and answer a request once it is complete
+    pub fn poll(&mut self, registers: &mut RegisterMap, serial: &mut impl Transmit) {
+        // The gap before a byte is detected by the receive interrupt, so frames received while
+        // a task was running are split correctly
+        while let Some((byte, frame_start)) = self.receiver.read_frame_byte() {
+            if frame_start {
+                self.answer(registers, serial);
+            }
+            self.frame.push(byte);
+        }
+
+        // Measured after reading so a byte received in the meantime does not end the frame
+        if modbus::is_frame_gap(self.receiver.silence().as_millis()) {
+            self.answer(registers, serial);
+        }
+    }
+
+    /// Answer the received request, if it is valid
+    fn answer(&mut self, registers: &mut RegisterMap, serial: &mut impl Transmit) {
+        if let Some(request) = self.frame.take() {
+            let mut response = [0; modbus::FRAME_SIZE];
+            if let Some(len) = self.slave.handle(request, registers, &mut response) {
+                serial.write_raw(&response[..len]);
+            }
+        }
+    }
+}
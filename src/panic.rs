@@ -0,0 +1,91 @@
+//! Panic handler that drives the outputs to a safe state and stores the panic location
+//!
+//! After a panic the handler starts the watchdog with a short timeout and waits for the reset,
+//! also if the panic happened before the application started the watchdog. The stored panic is
+//! reported on the next boot.
+
+use crate::chip;
+use crate::eeprom;
+use crate::hal;
+use crate::transmitter;
+
+/// Marks a stored panic in the EEPROM
+const PANIC_MARKER: u8 = 0xA5;
+
+/// Pins of the outputs on port D: burner inhibit PD6, pump buffer PD4, magnet valve buffer PD5
+const OUTPUT_PINS: u8 = (1 << 6) | (1 << 4) | (1 << 5);
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    avr_device::interrupt::disable();
+
+    // The peripherals are owned by the application. Nothing else runs anymore
+    let peripherals = unsafe { chip::Peripherals::steal() };
+
+    // Safe state: burner released, valve closed and pump off
+    peripherals
+        .PORTD
+        .portd
+        .modify(|r, w| unsafe { w.bits(r.bits() & !OUTPUT_PINS) });
+    peripherals
+        .PORTD
+        .ddrd
+        .modify(|r, w| unsafe { w.bits(r.bits() | OUTPUT_PINS) });
+
+    let line = info
+        .location()
+        .map(|location| location.line() as u16)
+        .unwrap_or(0);
+
+    // Report the panic if the serial port is already enabled. Queued messages are send first so
+    // the panic is not mixed into them
+    if peripherals.USART0.ucsr0b.read().txen0().bit_is_set() {
+        unsafe { transmitter::drain() };
+        let mut serial = PanicWriter {
+            usart: &peripherals.USART0,
+        };
+        if let Some(location) = info.location() {
+            ufmt::uwriteln!(&mut serial, "PANIC {}:{}", location.file(), line).ok();
+        } else {
+            ufmt::uwriteln!(&mut serial, "PANIC").ok();
+        }
+    }
+
+    let [low, high] = line.to_le_bytes();
+    let mut eeprom = eeprom::Eeprom::new(peripherals.EEPROM);
+    eeprom.write(eeprom::PANIC_ADDRESS, &[PANIC_MARKER, low, high]);
+
+    // Wait for the watchdog to reset the controller
+    let mut watchdog = hal::wdt::Wdt::new(peripherals.WDT, &peripherals.CPU.mcusr);
+    watchdog.start(hal::wdt::Timeout::Ms16).ok();
+    loop {}
+}
+
+/// Read the line of a stored panic and clear it
+pub fn take_stored(eeprom: &mut eeprom::Eeprom) -> Option<u16> {
+    let mut data = [0_u8; 3];
+    eeprom.read(eeprom::PANIC_ADDRESS, &mut data);
+    if data[0] == PANIC_MARKER {
+        eeprom.write_byte(eeprom::PANIC_ADDRESS, 0xFF);
+        Some(u16::from_le_bytes([data[1], data[2]]))
+    } else {
+        None
+    }
+}
+
+/// Blocking writer on the serial port without the hal
+struct PanicWriter<'a> {
+    usart: &'a chip::USART0,
+}
+
+impl ufmt::uWrite for PanicWriter<'_> {
+    type Error = void::Void;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        for byte in s.bytes() {
+            while self.usart.ucsr0a.read().udre0().bit_is_clear() {}
+            self.usart.udr0.write(|w| unsafe { w.bits(byte) });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! Control parameters that can be changed at runtime over the serial port
+
+use crate::timer::Duration;
+
+// Defaults of the parameters
+
+/// The buffer is used for heating while the buffer top is at least this warm
+pub const MIN_BUFFER_TEMPERATURE: i16 = 550; // 1/10 °C
+/// The buffer is enabled again once it is this much warmer than the minimum temperature
+pub const BUFFER_HYSTERESIS: i16 = 50; // 1/10 K
+
+/// Boiler must be at least this warm before it may load the buffer (anti condensation)
+pub const MIN_BOILER_TEMPERATURE: i16 = 600; // 1/10 °C
+/// Start loading the buffer if the boiler is this much warmer than the buffer bottom
+pub const LOADING_START_DIFFERENCE: i16 = 80; // 1/10 K
+/// Stop loading the buffer if the difference drops below this value
+pub const LOADING_STOP_DIFFERENCE: i16 = 30; // 1/10 K
+
+/// Frost protection is activated if any temperature falls below this value
+pub const FROST_TEMPERATURE: i16 = 50; // 1/10 °C
+/// Frost protection is released once the temperatures are above frost temperature plus hysteresis
+pub const FROST_HYSTERESIS: i16 = 30; // 1/10 K
+
+/// Temperatures are published again once they changed by this value
+pub const TEMPERATURE_DEADBAND: i16 = 5; // 1/10 K
+
+/// Warm water temperature that has to be reached by the legionella cycle
+pub const LEGIONELLA_TEMPERATURE: i16 = 600; // 1/10 °C
+/// Time the legionella temperature has to be held
+pub const LEGIONELLA_HOLD_TIME: i16 = 30; // min
+
+/// Parameters that can be read and written by name
+#[derive(Copy, Clone)]
+pub enum ParameterId {
+    MinBufferTemperature,
+    BufferHysteresis,
+    MinBoilerTemperature,
+    LoadingStartDifference,
+    LoadingStopDifference,
+    FrostTemperature,
+    FrostHysteresis,
+    TemperatureDeadband,
+    LegionellaTemperature,
+    LegionellaHoldTime,
+}
+
+/// All parameters in the order they are reported
+pub const PARAMETERS: [ParameterId; 10] = [
+    ParameterId::MinBufferTemperature,
+    ParameterId::BufferHysteresis,
+    ParameterId::MinBoilerTemperature,
+    ParameterId::LoadingStartDifference,
+    ParameterId::LoadingStopDifference,
+    ParameterId::FrostTemperature,
+    ParameterId::FrostHysteresis,
+    ParameterId::TemperatureDeadband,
+    ParameterId::LegionellaTemperature,
+    ParameterId::LegionellaHoldTime,
+];
+
+impl ParameterId {
+    /// Name used in the serial commands
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            ParameterId::MinBufferTemperature => "min_buffer",
+            ParameterId::BufferHysteresis => "buffer_hyst",
+            ParameterId::MinBoilerTemperature => "min_boiler",
+            ParameterId::LoadingStartDifference => "load_start",
+            ParameterId::LoadingStopDifference => "load_stop",
+            ParameterId::FrostTemperature => "frost",
+            ParameterId::FrostHysteresis => "frost_hyst",
+            ParameterId::TemperatureDeadband => "temp_deadband",
+            ParameterId::LegionellaTemperature => "legionella",
+            ParameterId::LegionellaHoldTime => "legionella_hold",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PARAMETERS.iter().find(|id| id.to_string() == name).copied()
+    }
+
+    /// Smallest and largest allowed value
+    fn limits(&self) -> (i16, i16) {
+        match self {
+            ParameterId::MinBufferTemperature => (200, 900), // 1/10 °C
+            ParameterId::BufferHysteresis => (10, 200),      // 1/10 K
+            ParameterId::MinBoilerTemperature => (300, 900), // 1/10 °C
+            ParameterId::LoadingStartDifference => (10, 300), // 1/10 K
+            ParameterId::LoadingStopDifference => (0, 300),  // 1/10 K
+            ParameterId::FrostTemperature => (0, 150),       // 1/10 °C
+            ParameterId::FrostHysteresis => (10, 100),       // 1/10 K
+            ParameterId::TemperatureDeadband => (1, 50),     // 1/10 K
+            ParameterId::LegionellaTemperature => (550, 800), // 1/10 °C
+            ParameterId::LegionellaHoldTime => (1, 120),     // min, below the timeout of the cycle
+        }
+    }
+}
+
+/// A value is out of range or the loading would stop before it is started
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InvalidValue;
+
+/// Parameters of the control and telemetry. Temperatures in 1/10 °C, differences in 1/10 K,
+/// times in min
+#[derive(Copy, Clone)]
+pub struct Parameters {
+    pub min_buffer_temperature: i16,
+    pub buffer_hysteresis: i16,
+    pub min_boiler_temperature: i16,
+    pub loading_start_difference: i16,
+    pub loading_stop_difference: i16,
+    pub frost_temperature: i16,
+    pub frost_hysteresis: i16,
+    /// Change of a temperature that is published before the keep alive
+    pub temperature_deadband: i16,
+    pub legionella_temperature: i16,
+    pub legionella_hold_time: i16,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            min_buffer_temperature: MIN_BUFFER_TEMPERATURE,
+            buffer_hysteresis: BUFFER_HYSTERESIS,
+            min_boiler_temperature: MIN_BOILER_TEMPERATURE,
+            loading_start_difference: LOADING_START_DIFFERENCE,
+            loading_stop_difference: LOADING_STOP_DIFFERENCE,
+            frost_temperature: FROST_TEMPERATURE,
+            frost_hysteresis: FROST_HYSTERESIS,
+            temperature_deadband: TEMPERATURE_DEADBAND,
+            legionella_temperature: LEGIONELLA_TEMPERATURE,
+            legionella_hold_time: LEGIONELLA_HOLD_TIME,
+        }
+    }
+}
+
+impl Parameters {
+    pub fn get(&self, id: ParameterId) -> i16 {
+        match id {
+            ParameterId::MinBufferTemperature => self.min_buffer_temperature,
+            ParameterId::BufferHysteresis => self.buffer_hysteresis,
+            ParameterId::MinBoilerTemperature => self.min_boiler_temperature,
+            ParameterId::LoadingStartDifference => self.loading_start_difference,
+            ParameterId::LoadingStopDifference => self.loading_stop_difference,
+            ParameterId::FrostTemperature => self.frost_temperature,
+            ParameterId::FrostHysteresis => self.frost_hysteresis,
+            ParameterId::TemperatureDeadband => self.temperature_deadband,
+            ParameterId::LegionellaTemperature => self.legionella_temperature,
+            ParameterId::LegionellaHoldTime => self.legionella_hold_time,
+        }
+    }
+
+    /// Time the legionella temperature has to be held
+    pub fn legionella_hold(&self) -> Duration {
+        Duration::from_secs(self.legionella_hold_time as u32 * 60)
+    }
+
+    /// Set a parameter. The value is rejected if it is out of range or the loading would stop
+    /// before it is started
+    pub fn set(&mut self, id: ParameterId, value: i16) -> Result<(), InvalidValue> {
+        self.set_all(core::iter::once((id, value)))
+    }
+
+    /// Set several parameters at once. Every value is checked against its range, the loading
+    /// start and stop difference only after all values are set, so their order does not matter.
+    /// If a value is rejected none of them is set
+    pub fn set_all(
+        &mut self,
+        values: impl Iterator<Item = (ParameterId, i16)>,
+    ) -> Result<(), InvalidValue> {
+        let mut parameters = *self;
+        for (id, value) in values {
+            let (min, max) = id.limits();
+            if value < min || value > max {
+                return Err(InvalidValue);
+            }
+            match id {
+                ParameterId::MinBufferTemperature => parameters.min_buffer_temperature = value,
+                ParameterId::BufferHysteresis => parameters.buffer_hysteresis = value,
+                ParameterId::MinBoilerTemperature => parameters.min_boiler_temperature = value,
+                ParameterId::LoadingStartDifference => parameters.loading_start_difference = value,
+                ParameterId::LoadingStopDifference => parameters.loading_stop_difference = value,
+                ParameterId::FrostTemperature => parameters.frost_temperature = value,
+                ParameterId::FrostHysteresis => parameters.frost_hysteresis = value,
+                ParameterId::TemperatureDeadband => parameters.temperature_deadband = value,
+                ParameterId::LegionellaTemperature => parameters.legionella_temperature = value,
+                ParameterId::LegionellaHoldTime => parameters.legionella_hold_time = value,
+            }
+        }
+        if parameters.loading_stop_difference >= parameters.loading_start_difference {
+            return Err(InvalidValue);
+        }
+
+        *self = parameters;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! Interrupt driven receiver of the serial port
+//!
+//! The received bytes are collected in a ring buffer by the receive interrupt and read in the
+//! main loop. For Modbus the interrupt also marks the bytes that follow a frame gap, as the main
+//! loop may read them long after they were received.
+
+use atmega_hal as hal;
+use hal::{
+    pac::USART0,
+    port::{
+        mode::{Input, Output},
+        Pin, PD0, PD1,
+    },
+};
+
+use crate::chip;
+#[cfg(feature = "modbus")]
+use crate::modbus;
+#[cfg(feature = "modbus")]
+use crate::modbus_port;
+#[cfg(feature = "modbus")]
+use crate::timer::Duration;
+use crate::timer::{self, Instant};
+
+type UsartRead = hal::usart::UsartReader<USART0, Pin<Input, PD0>, Pin<Output, PD1>, super::Clock>;
+
+/// Size of the receive ring buffer. Has to be a power of two
+const RX_BUFFER_SIZE: usize = 64;
+
+/// Bytes received by the interrupt. Only accessed in interrupt free sections
+static mut RX_BUFFER: RingBuffer = RingBuffer {
+    data: [0; RX_BUFFER_SIZE],
+    head: 0,
+    tail: 0,
+    #[cfg(feature = "modbus")]
+    frame_starts: [0; RX_BUFFER_SIZE / 8],
+    overflows: 0,
+    last_received: Instant::from_millis(0),
+};
+
+struct RingBuffer {
+    data: [u8; RX_BUFFER_SIZE],
+    /// Next position to write
+    head: usize,
+    /// Next position to read
+    tail: usize,
+    /// One bit per position, set if the byte starts a Modbus frame
+    #[cfg(feature = "modbus")]
+    frame_starts: [u8; RX_BUFFER_SIZE / 8],
+    /// Number of bytes dropped because the buffer was full
+    overflows: u16,
+    /// Time the last byte was received
+    last_received: Instant,
+}
+
+impl RingBuffer {
+    fn push(&mut self, byte: u8) {
+        let next = (self.head + 1) % RX_BUFFER_SIZE;
+        if next == self.tail {
+            self.overflows = self.overflows.wrapping_add(1);
+        } else {
+            self.data[self.head] = byte;
+            self.head = next;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.head == self.tail {
+            None
+        } else {
+            let byte = self.data[self.tail];
+            self.tail = (self.tail + 1) % RX_BUFFER_SIZE;
+            Some(byte)
+        }
+    }
+
+    #[cfg(feature = "modbus")]
+    fn set_frame_start(&mut self, index: usize, frame_start: bool) {
+        let mask = 1 << (index % 8);
+        if frame_start {
+            self.frame_starts[index / 8] |= mask;
+        } else {
+            self.frame_starts[index / 8] &= !mask;
+        }
+    }
+
+    #[cfg(feature = "modbus")]
+    fn is_frame_start(&self, index: usize) -> bool {
+        self.frame_starts[index / 8] & (1 << (index % 8)) != 0
+    }
+}
+
+pub struct Receiver {
+    /// Owned to guarantee exclusive access to the receiver. The bytes are read in the interrupt
+    _serial: UsartRead,
+}
+
+impl Receiver {
+    /// Create the receiver and enable the receive interrupt
+    pub fn new(serial: UsartRead) -> Self {
+        // The hal does not expose the interrupt enable of the receiver
+        let usart = unsafe { &*chip::USART0::ptr() };
+        usart.ucsr0b.modify(|_, w| w.rxcie0().set_bit());
+
+        Self { _serial: serial }
+    }
+
+    /// Take the next received byte
+    pub fn read(&mut self) -> Option<u8> {
+        avr_device::interrupt::free(|_| unsafe { RX_BUFFER.pop() })
+    }
+
+    /// Number of received bytes that were dropped because the ring buffer was full
+    pub fn overflows(&self) -> u16 {
+        let mut overflows = 0;
+        avr_device::interrupt::free(|_| overflows = unsafe { RX_BUFFER.overflows });
+        overflows
+    }
+}
+
+#[cfg(feature = "modbus")]
+impl modbus_port::Receive for Receiver {
+    fn read_frame_byte(&mut self) -> Option<(u8, bool)> {
+        avr_device::interrupt::free(|_| unsafe {
+            let frame_start = RX_BUFFER.is_frame_start(RX_BUFFER.tail);
+            RX_BUFFER.pop().map(|byte| (byte, frame_start))
+        })
+    }
+
+    fn silence(&self) -> Duration {
+        let mut last_received = Instant::from_millis(0);
+        avr_device::interrupt::free(|_| last_received = unsafe { RX_BUFFER.last_received });
+        last_received.elapsed()
+    }
+
+    fn overflows(&self) -> u16 {
+        Receiver::overflows(self)
+    }
+}
+
+#[avr_device::interrupt(atmega328p)]
+unsafe fn USART_RX() {
+    let usart = &*chip::USART0::ptr();
+    let byte = usart.udr0.read().bits();
+    let now = timer::now_in_interrupt();
+
+    // Marks the position of the byte. If the buffer is full the byte is dropped and the mark is
+    // overwritten by the next byte
+    #[cfg(feature = "modbus")]
+    RX_BUFFER.set_frame_start(
+        RX_BUFFER.head,
+        modbus::is_frame_gap((now - RX_BUFFER.last_received).as_millis()),
+    );
+    RX_BUFFER.push(byte);
+    RX_BUFFER.last_received = now;
+}
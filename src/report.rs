@@ -0,0 +1,258 @@
+//! Report by exception
+//!
+//! Values are only published when they differ from the value published last. Temperatures have
+//! to change by at least the deadband, all other values on every change. After the keep alive
+//! interval all values are published again, so new subscribers get every value and lost
+//! messages are repaired. Counters and diagnostics only change slowly and are published with the
+//! keep alive only.
+//!
+//! The keep alive is split into parts that are published in consecutive cycles, so no cycle waits
+//! long for the transmit buffer of the serial port.
+//!
+//! The JSON snapshot combines temperatures, IOs and state in one message of about 300 bytes. It
+//! is send at most once per cycle and only if one of its values changed, so with the telemetry
+//! period of 1 s it takes up to a third of the 9600 baud line while the plant is switching.
+//! Changes of the temperatures alone are limited by the deadband.
+
+use crate::time::{Duration, Instant};
+
+/// Stored for missing temperatures
+const MISSING: i16 = i16::MIN;
+
+/// Values that are published on change
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Value {
+    BufferTop,
+    BufferBottom,
+    WarmWater,
+    Boiler,
+    StartBurner,
+    WarmWaterPump,
+    HeatingPump,
+    BurnerInhibit,
+    MagnetValveBuffer,
+    PumpBuffer,
+    State,
+    Display,
+    Alarm,
+    Legionella,
+}
+
+const VALUES: usize = 14;
+
+/// Parts of the keep alive in the order they are published
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Part {
+    Temperatures,
+    Inputs,
+    Outputs,
+    /// State, display, alarm and legionella
+    Status,
+    Counters,
+    /// Counters of the output with this index in `io::OUTPUTS`
+    Switches(usize),
+    /// Statistics of the task with this index in `scheduler::TASKS`
+    Task(usize),
+    Diagnostics,
+}
+
+impl Part {
+    fn next(self, outputs: usize, tasks: usize) -> Option<Part> {
+        match self {
+            Part::Temperatures => Some(Part::Inputs),
+            Part::Inputs => Some(Part::Outputs),
+            Part::Outputs => Some(Part::Status),
+            Part::Status => Some(Part::Counters),
+            Part::Counters if outputs > 0 => Some(Part::Switches(0)),
+            Part::Switches(index) if index + 1 < outputs => Some(Part::Switches(index + 1)),
+            Part::Counters | Part::Switches(_) if tasks > 0 => Some(Part::Task(0)),
+            Part::Task(index) if index + 1 < tasks => Some(Part::Task(index + 1)),
+            Part::Counters | Part::Switches(_) | Part::Task(_) => Some(Part::Diagnostics),
+            Part::Diagnostics => None,
+        }
+    }
+}
+
+impl Value {
+    /// Topic of the value in the MQTT messages
+    pub fn topic(&self) -> &'static str {
+        match self {
+            Value::BufferTop => "Temperature/Puffer_Oben",
+            Value::BufferBottom => "Temperature/Puffer_Unten",
+            Value::WarmWater => "Temperature/Warmwasser",
+            Value::Boiler => "Temperature/Kessel",
+            Value::StartBurner => "Inputs/BrennerStart",
+            Value::WarmWaterPump => "Inputs/Pumpe_Warmwasser",
+            Value::HeatingPump => "Inputs/Pumpe_Heizung",
+            Value::BurnerInhibit => "Outputs/Brenner_Sperre",
+            Value::MagnetValveBuffer => "Outputs/Magnetventil_Puffer",
+            Value::PumpBuffer => "Outputs/Pumpe_Puffer",
+            Value::State => "State",
+            Value::Display => "Display",
+            Value::Alarm => "Alarm",
+            Value::Legionella => "Legionella/Result",
+        }
+    }
+
+    /// Part of the keep alive that publishes the value
+    fn part(&self) -> Part {
+        match self {
+            Value::BufferTop | Value::BufferBottom | Value::WarmWater | Value::Boiler => {
+                Part::Temperatures
+            }
+            Value::StartBurner | Value::WarmWaterPump | Value::HeatingPump => Part::Inputs,
+            Value::BurnerInhibit | Value::MagnetValveBuffer | Value::PumpBuffer => Part::Outputs,
+            Value::State | Value::Display | Value::Alarm | Value::Legionella => Part::Status,
+        }
+    }
+}
+
+pub struct Reporter {
+    keep_alive: Duration,
+    /// Number of outputs and tasks with a part of the keep alive each
+    outputs: usize,
+    tasks: usize,
+    /// Time the keep alive was started last. None before the first cycle
+    last_keep_alive: Option<Instant>,
+    /// Part of the keep alive published in the current cycle
+    part: Option<Part>,
+    /// Last published values. Booleans are stored as 0 and 1
+    last: [i16; VALUES],
+}
+
+impl Reporter {
+    pub fn new(keep_alive: Duration, outputs: usize, tasks: usize) -> Self {
+        Self {
+            keep_alive,
+            outputs,
+            tasks,
+            last_keep_alive: None,
+            part: None,
+            last: [MISSING; VALUES],
+        }
+    }
+
+    /// Start a cycle. Returns the part of the keep alive that is published in this cycle
+    pub fn start(&mut self, time: Instant) -> Option<Part> {
+        let due = match self.last_keep_alive {
+            Some(last) => time - last >= self.keep_alive,
+            None => true,
+        };
+        self.part = if due {
+            self.last_keep_alive = Some(time);
+            Some(Part::Temperatures)
+        } else {
+            self.part
+                .and_then(|part| part.next(self.outputs, self.tasks))
+        };
+        self.part
+    }
+
+    /// Start the keep alive in the next cycle, e.g. after the format was changed
+    pub fn force_all(&mut self) {
+        self.last_keep_alive = None;
+    }
+
+    /// Check if a temperature changed by at least the deadband
+    #[cfg(not(feature = "binary"))]
+    pub fn temperature(&mut self, value: Value, temperature: Option<i16>, deadband: i16) -> bool {
+        self.changed(&[temperature_entry(&(value, temperature))], deadband)
+    }
+
+    pub fn flag(&mut self, value: Value, flag: bool) -> bool {
+        self.changed(&[flag_entry(&(value, flag))], 1)
+    }
+
+    /// Check if a value without deadband, e.g. the id of the state, changed
+    pub fn value(&mut self, value: Value, new: i16) -> bool {
+        self.changed(&[(value, new)], 1)
+    }
+
+    /// Check if any temperature changed by at least the deadband. All temperatures are marked as
+    /// published in this case
+    #[cfg(feature = "binary")]
+    pub fn temperatures(&mut self, temperatures: &[(Value, Option<i16>)], deadband: i16) -> bool {
+        let changed = self.forced(temperatures.iter().map(temperature_entry))
+            || self.differs(temperatures.iter().map(temperature_entry), deadband);
+        if changed {
+            self.store(temperatures.iter().map(temperature_entry));
+        }
+        changed
+    }
+
+    /// Check if any flag, e.g. of the inputs and outputs, changed. All of them are marked as
+    /// published in this case
+    #[cfg(feature = "binary")]
+    pub fn flags(&mut self, flags: &[(Value, bool)]) -> bool {
+        let changed = self.forced(flags.iter().map(flag_entry))
+            || self.differs(flags.iter().map(flag_entry), 1);
+        if changed {
+            self.store(flags.iter().map(flag_entry));
+        }
+        changed
+    }
+
+    /// Check if any value of a snapshot with temperatures, IOs and state changed. All of them are
+    /// marked as published in this case. The snapshot is published with the first part of the
+    /// keep alive
+    #[cfg(not(feature = "binary"))]
+    pub fn snapshot(
+        &mut self,
+        temperatures: &[(Value, Option<i16>)],
+        io: &[(Value, bool)],
+        state: u8,
+        deadband: i16,
+    ) -> bool {
+        let state = [(Value::State, state as i16)];
+        let changed = self.part == Some(Part::Temperatures)
+            || self.differs(temperatures.iter().map(temperature_entry), deadband)
+            || self.differs(io.iter().map(flag_entry), 1)
+            || self.differs(state.iter().copied(), 1);
+        if changed {
+            self.store(temperatures.iter().map(temperature_entry));
+            self.store(io.iter().map(flag_entry));
+            self.store(state.iter().copied());
+        }
+        changed
+    }
+
+    /// Check if any of the values changed and mark all of them as published if so
+    fn changed(&mut self, values: &[(Value, i16)], threshold: i16) -> bool {
+        let changed =
+            self.forced(values.iter().copied()) || self.differs(values.iter().copied(), threshold);
+        if changed {
+            self.store(values.iter().copied());
+        }
+        changed
+    }
+
+    /// Check if any of the values is published by the current part of the keep alive
+    fn forced(&self, mut values: impl Iterator<Item = (Value, i16)>) -> bool {
+        values.any(|(value, _)| Some(value.part()) == self.part)
+    }
+
+    /// Check if any of the values differs by at least `threshold` from the published one
+    fn differs(&self, mut values: impl Iterator<Item = (Value, i16)>, threshold: i16) -> bool {
+        values.any(|(value, new)| {
+            let old = self.last[value as usize];
+            if (old == MISSING) != (new == MISSING) {
+                return true;
+            }
+            (new as i32 - old as i32).abs() >= threshold.max(1) as i32
+        })
+    }
+
+    fn store(&mut self, values: impl Iterator<Item = (Value, i16)>) {
+        for (value, new) in values {
+            self.last[value as usize] = new;
+        }
+    }
+}
+
+fn temperature_entry((value, temperature): &(Value, Option<i16>)) -> (Value, i16) {
+    (*value, temperature.unwrap_or(MISSING))
+}
+
+fn flag_entry((value, flag): &(Value, bool)) -> (Value, i16) {
+    (*value, *flag as i16)
+}
@@ -0,0 +1,101 @@
+//! Cause of the last reset and the number of resets per cause
+
+use crate::chip;
+use crate::eeprom;
+
+const CAUSE_COUNT: usize = 5;
+/// Number of bytes in the EEPROM including the crc
+const STORE_SIZE: usize = 2 * CAUSE_COUNT + 1;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ResetCause {
+    PowerOn,
+    External,
+    BrownOut,
+    Watchdog,
+    Unknown,
+}
+
+/// All reset causes in the order they are stored
+pub const CAUSES: [ResetCause; CAUSE_COUNT] = [
+    ResetCause::PowerOn,
+    ResetCause::External,
+    ResetCause::BrownOut,
+    ResetCause::Watchdog,
+    ResetCause::Unknown,
+];
+
+impl ResetCause {
+    /// Read the reset cause from the MCU status register and clear the flags
+    pub fn read(mcusr: &chip::cpu::MCUSR) -> Self {
+        let flags = mcusr.read();
+        let cause = if flags.porf().bit_is_set() {
+            // Other flags may be set on power on as well
+            ResetCause::PowerOn
+        } else if flags.borf().bit_is_set() {
+            ResetCause::BrownOut
+        } else if flags.extrf().bit_is_set() {
+            ResetCause::External
+        } else if flags.wdrf().bit_is_set() {
+            ResetCause::Watchdog
+        } else {
+            ResetCause::Unknown
+        };
+        mcusr.write(|w| unsafe { w.bits(0) });
+        cause
+    }
+
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "PowerOn",
+            ResetCause::External => "External",
+            ResetCause::BrownOut => "BrownOut",
+            ResetCause::Watchdog => "Watchdog",
+            ResetCause::Unknown => "Unknown",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            ResetCause::PowerOn => 0,
+            ResetCause::External => 1,
+            ResetCause::BrownOut => 2,
+            ResetCause::Watchdog => 3,
+            ResetCause::Unknown => 4,
+        }
+    }
+}
+
+/// Number of resets for each cause, persisted in the EEPROM
+pub struct ResetCounters {
+    counts: [u16; CAUSE_COUNT],
+}
+
+impl ResetCounters {
+    /// Load the counters from the EEPROM, count the reset and store the counters again
+    pub fn count(eeprom: &mut eeprom::Eeprom, cause: ResetCause) -> Self {
+        let mut data = [0_u8; STORE_SIZE];
+        eeprom.read(eeprom::RESET_COUNTERS_ADDRESS, &mut data);
+
+        let mut counts = [0_u16; CAUSE_COUNT];
+        if eeprom::checksum(&data[..STORE_SIZE - 1]) == data[STORE_SIZE - 1] {
+            for (count, bytes) in counts.iter_mut().zip(data.chunks(2)) {
+                *count = u16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+        }
+
+        counts[cause.index()] = counts[cause.index()].saturating_add(1);
+
+        for (bytes, count) in data.chunks_mut(2).zip(counts.iter()) {
+            bytes.copy_from_slice(&count.to_le_bytes());
+        }
+        data[STORE_SIZE - 1] = eeprom::checksum(&data[..STORE_SIZE - 1]);
+        eeprom.write(eeprom::RESET_COUNTERS_ADDRESS, &data);
+
+        Self { counts }
+    }
+
+    pub fn get(&self, cause: ResetCause) -> u16 {
+        self.counts[cause.index()]
+    }
+}
@@ -0,0 +1,222 @@
+//! Cooperative scheduler for the periodic tasks of the main loop
+
+use crate::timer::{Duration, Instant};
+
+/// Time over which the load is measured. Short compared to the wrap of the timer
+const LOAD_WINDOW: Duration = Duration::from_secs(60);
+
+/// Tasks of the main loop. Tasks with a lower index run first if several tasks are due
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TaskId {
+    Sensors,
+    Control,
+    Display,
+    Telemetry,
+    Debug,
+}
+
+const TASK_COUNT: usize = 5;
+
+/// All tasks in the order of their priority
+pub const TASKS: [TaskId; TASK_COUNT] = [
+    TaskId::Sensors,
+    TaskId::Control,
+    TaskId::Display,
+    TaskId::Telemetry,
+    TaskId::Debug,
+];
+
+impl TaskId {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            TaskId::Sensors => "Sensors",
+            TaskId::Control => "Control",
+            TaskId::Display => "Display",
+            TaskId::Telemetry => "Telemetry",
+            TaskId::Debug => "Debug",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            TaskId::Sensors => 0,
+            TaskId::Control => 1,
+            TaskId::Display => 2,
+            TaskId::Telemetry => 3,
+            TaskId::Debug => 4,
+        }
+    }
+}
+
+/// Timing of a task
+#[derive(Copy, Clone)]
+pub struct TaskConfig {
+    /// Time between two runs
+    pub period: Duration,
+    /// Time after the task got due until it has to be finished
+    pub deadline: Duration,
+}
+
+/// Execution time statistics of a task
+#[derive(Copy, Clone, Default)]
+pub struct TaskStats {
+    /// Shortest run
+    pub min: Duration,
+    /// Longest run
+    pub max: Duration,
+    /// Number of missed deadlines
+    pub overruns: u32,
+    /// Sum of the run times in ms. Halved together with `runs` before it overflows
+    total: u32,
+    runs: u32,
+}
+
+impl TaskStats {
+    /// Mean run time
+    pub fn average(&self) -> Duration {
+        Duration::from_millis(self.total.checked_div(self.runs).unwrap_or(0))
+    }
+
+    fn add(&mut self, run_time: Duration) {
+        if self.runs == 0 || run_time < self.min {
+            self.min = run_time;
+        }
+        if run_time > self.max {
+            self.max = run_time;
+        }
+        if self.total.checked_add(run_time.as_millis()).is_none() {
+            self.total /= 2;
+            self.runs /= 2;
+        }
+        self.total += run_time.as_millis();
+        self.runs += 1;
+    }
+}
+
+struct Task {
+    config: TaskConfig,
+    /// Time the task was due the last time
+    due: Instant,
+    /// Run the task at the next poll independent of the period
+    triggered: bool,
+    stats: TaskStats,
+}
+
+impl Task {
+    fn is_due(&self, time: Instant) -> bool {
+        self.triggered || time - self.due >= self.config.period
+    }
+}
+
+pub struct Scheduler {
+    tasks: [Task; TASK_COUNT],
+    /// Task that is running, the time it got due and the time it was started
+    running: Option<(TaskId, Instant, Instant)>,
+    /// Start of the current load window
+    window_start: Instant,
+    /// Time spent in tasks since `window_start`
+    busy: Duration,
+    /// Load of the last complete window in %. None until the first window is complete
+    last_load: Option<u32>,
+}
+
+impl Scheduler {
+    /// Create the scheduler. The configs are given in the order of `TASKS`. All tasks are due
+    /// at the first poll
+    pub fn new(time: Instant, configs: [TaskConfig; TASK_COUNT]) -> Self {
+        let task = |index: usize| Task {
+            config: configs[index],
+            due: time - configs[index].period,
+            triggered: false,
+            stats: TaskStats::default(),
+        };
+        Self {
+            tasks: [task(0), task(1), task(2), task(3), task(4)],
+            running: None,
+            window_start: time,
+            busy: Duration::default(),
+            last_load: None,
+        }
+    }
+
+    /// Get the next task to run. The task has to be finished with `finish` before the next poll
+    pub fn poll(&mut self, time: Instant) -> Option<TaskId> {
+        let id = *TASKS
+            .iter()
+            .find(|id| self.tasks[id.index()].is_due(time))?;
+
+        let task = &mut self.tasks[id.index()];
+        // Triggered tasks are treated as if they got due just now
+        let due = if task.triggered {
+            time
+        } else {
+            task.due + task.config.period
+        };
+        task.triggered = false;
+        // Keep the period without drift. Skip runs that were missed completely
+        task.due = if time - due >= task.config.period {
+            time
+        } else {
+            due
+        };
+
+        self.running = Some((id, due, time));
+        Some(id)
+    }
+
+    /// Finish the running task. Returns true if the task missed its deadline
+    pub fn finish(&mut self, time: Instant) -> bool {
+        if let Some((id, due, start)) = self.running.take() {
+            let run_time = time - start;
+            self.busy = self.busy + run_time;
+            if time - self.window_start >= LOAD_WINDOW {
+                self.last_load = Some(self.window_load(time));
+                self.window_start = time;
+                self.busy = Duration::default();
+            }
+
+            let task = &mut self.tasks[id.index()];
+            task.stats.add(run_time);
+            if time - due > task.config.deadline {
+                task.stats.overruns = task.stats.overruns.wrapping_add(1);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Run a task at the next poll
+    pub fn trigger(&mut self, id: TaskId) {
+        self.tasks[id.index()].triggered = true;
+    }
+
+    /// Execution time statistics of a task since startup or the last reset
+    pub fn stats(&self, id: TaskId) -> TaskStats {
+        self.tasks[id.index()].stats
+    }
+
+    /// Share of the time spent in tasks in %. Measured over the last complete window, until the
+    /// first window is complete over the current one
+    pub fn load(&self, time: Instant) -> u32 {
+        self.last_load.unwrap_or_else(|| self.window_load(time))
+    }
+
+    /// Load of the current window in %
+    fn window_load(&self, time: Instant) -> u32 {
+        let elapsed = (time - self.window_start).as_millis() / 100;
+        self.busy
+            .as_millis()
+            .checked_div(elapsed)
+            .map_or(0, |load| load.min(100))
+    }
+
+    /// Restart the statistics of all tasks
+    pub fn reset_stats(&mut self, time: Instant) {
+        for task in self.tasks.iter_mut() {
+            task.stats = TaskStats::default();
+        }
+        self.window_start = time;
+        self.busy = Duration::default();
+        self.last_load = None;
+    }
+}
@@ -1,85 +1,435 @@
-use atmega_hal as hal;
-use hal::{
-    pac::USART0,
-    port::{
-        mode::{Input, Output},
-        Pin, PD0, PD1,
-    },
-};
+//! Logging and telemetry over the serial port
+//!
+//! Every message is send as one framed line so the host can detect corrupted or lost messages
+//! and distinguish telemetry from logs:
+//!
+//! `#<version>;<sequence>;<type>;<payload>*<crc>`
+//!
+//! - version: protocol version, currently `1`
+//! - sequence: decimal counter incremented with every frame, wraps at 65535
+//! - type: `D` debug, `I` info, `W` warning, `E` error, `M` MQTT, `J` JSON snapshot,
+//!   `A` Home Assistant discovery, `R` reply to a command
+//! - payload: free text. MQTT messages use `<topic>:=<value>`, snapshots a single JSON object and
+//!   discovery messages `<component>/<object id>:=<config>`
+//! - crc: CRC-16/CCITT-FALSE over everything between `#` and `*` as four upper case hex digits
+//!
+//! Lines that are not framed (e.g. from the panic handler) are plain text.
+//!
+//! Messages are queued in the transmit buffer of the `transmitter` module and send in the
+//! background.
+//!
+//! Debug, info and MQTT messages can be switched on and off at runtime. Warnings, errors and
+//! replies are always send.
+//!
+//! With the feature `binary` all messages are send in the binary format of the `binary` module
+//! instead. A muted logger sends no messages at all, e.g. while the port is used for Modbus.
 
-type UsartWrite = hal::usart::UsartWriter<USART0, Pin<Input, PD0>, Pin<Output, PD1>, super::Clock>;
+use crc_any::CRCu16;
+use ufmt::uWrite;
 
+#[cfg(feature = "binary")]
+use crate::binary;
+use crate::eeprom;
+use crate::io::{Inputs, Outputs};
+#[cfg(feature = "modbus")]
+use crate::modbus_port;
+use crate::temperature::PlantTemperatures;
+use crate::transmitter::Transmitter;
+
+/// Version of the frame format
+const PROTOCOL_VERSION: u8 = 1;
+
+/// Write a formatted message as one frame
+macro_rules! frame {
+    ($logger:expr, $kind:expr, $($arg:tt)*) => {{
+        let mut frame = $logger.begin_frame($kind);
+        ufmt::uwrite!(&mut frame, $($arg)*).ok();
+        frame.end();
+    }};
+}
+
+/// Size of the log channels in the EEPROM including the crc
+const STORE_SIZE: usize = 2;
+
+/// Log channels that can be switched at runtime
+#[derive(Copy, Clone)]
+pub enum Channel {
+    Debug,
+    Info,
+    Mqtt,
+}
+
+/// All channels in the order of their bits in the EEPROM
+pub const CHANNELS: [Channel; 3] = [Channel::Debug, Channel::Info, Channel::Mqtt];
+
+impl Channel {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            Channel::Debug => "debug",
+            Channel::Info => "info",
+            Channel::Mqtt => "mqtt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CHANNELS
+            .iter()
+            .find(|channel| channel.to_string() == name)
+            .copied()
+    }
+
+    fn bit(&self) -> u8 {
+        match self {
+            Channel::Debug => 1 << 0,
+            Channel::Info => 1 << 1,
+            Channel::Mqtt => 1 << 2,
+        }
+    }
+}
+
+/// Format of the telemetry
+#[derive(Copy, Clone, PartialEq)]
+pub enum Format {
+    /// One MQTT message per value
+    Topics,
+    /// Temperatures, IOs and state in one JSON snapshot
+    Json,
+}
+
+/// Type of a message
+#[derive(Copy, Clone)]
+enum Kind {
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Mqtt,
+    Json,
+    Announce,
+    Reply,
+}
+
+impl Kind {
+    fn to_str(self) -> &'static str {
+        match self {
+            Kind::Debug => "D",
+            Kind::Info => "I",
+            Kind::Warn => "W",
+            Kind::Error => "E",
+            Kind::Mqtt => "M",
+            Kind::Json => "J",
+            Kind::Announce => "A",
+            Kind::Reply => "R",
+        }
+    }
+}
+
+/// Writes a single frame and calculates its checksum
+struct Frame<'a> {
+    serial: &'a mut Transmitter,
+    crc: CRCu16,
+    /// Nothing is send if muted
+    muted: bool,
+    /// Binary messages are encoded as a whole at the end
+    #[cfg(feature = "binary")]
+    buffer: binary::Buffer,
+}
+
+impl Frame<'_> {
+    /// Append data to a binary message
+    #[cfg(feature = "binary")]
+    fn push(&mut self, data: &[u8]) {
+        let data = self.buffer.push(data);
+        self.crc.digest(data);
+    }
+
+    /// Append the checksum and send the binary message
+    #[cfg(feature = "binary")]
+    fn end(mut self) {
+        if self.muted {
+            return;
+        }
+        let serial = self.serial;
+        let message = self.buffer.finish(self.crc.get_crc());
+        binary::write_cobs(message, |byte| serial.write_byte(byte));
+    }
+
+    /// Write the checksum and terminate the line
+    #[cfg(not(feature = "binary"))]
+    fn end(self) {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+        if self.muted {
+            return;
+        }
+        let crc = self.crc.get_crc();
+        self.serial.write_char('*').ok();
+        for shift in [12, 8, 4, 0].iter() {
+            let digit = HEX[((crc >> shift) & 0x0F) as usize];
+            self.serial.write_char(digit as char).ok();
+        }
+        self.serial.write_char('\n').ok();
+    }
+}
+
+impl Frame<'_> {
+    /// Write an object with temperatures in °C. Missing temperatures are null
+    fn temperatures(&mut self, values: &[(&str, Option<i16>)]) {
+        self.write_char('{').ok();
+        for (index, (name, value)) in values.iter().enumerate() {
+            if index > 0 {
+                self.write_char(',').ok();
+            }
+            ufmt::uwrite!(self, "\"{}\":", name).ok();
+            match value {
+                Some(value) => {
+                    let sign = if *value < 0 { "-" } else { "" };
+                    let value = (*value as i32).abs();
+                    ufmt::uwrite!(self, "{}{}.{}", sign, value / 10, value % 10).ok();
+                }
+                None => {
+                    self.write_str("null").ok();
+                }
+            }
+        }
+        self.write_char('}').ok();
+    }
+
+    /// Write a topic with all `/` replaced by `_`
+    fn object_id(&mut self, topic: &str) {
+        for c in topic.chars() {
+            self.write_char(if c == '/' { '_' } else { c }).ok();
+        }
+    }
+
+    /// Write an object with boolean values
+    fn bools(&mut self, values: &[(&str, bool)]) {
+        self.write_char('{').ok();
+        for (index, (name, value)) in values.iter().enumerate() {
+            if index > 0 {
+                self.write_char(',').ok();
+            }
+            ufmt::uwrite!(self, "\"{}\":{}", name, value).ok();
+        }
+        self.write_char('}').ok();
+    }
+}
+
+impl ufmt::uWrite for Frame<'_> {
+    type Error = void::Void;
+
+    #[cfg(feature = "binary")]
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+
+    #[cfg(not(feature = "binary"))]
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        if self.muted {
+            return Ok(());
+        }
+        self.crc.digest(s.as_bytes());
+        self.serial.write_str(s)
+    }
+}
 #[allow(dead_code)]
 pub struct SerialLogger {
     debug: bool,
     info: bool,
     mqtt: bool,
-    serial: UsartWrite,
+    format: Format,
+    muted: bool,
+    serial: Transmitter,
+    /// Sequence number of the next frame
+    sequence: u16,
 }
 
 #[allow(dead_code)]
 impl SerialLogger {
-    pub fn new(serial: UsartWrite, debug: bool, info: bool, mqtt: bool) -> Self {
+    pub fn new(serial: Transmitter, debug: bool, info: bool, mqtt: bool) -> Self {
         Self {
             debug,
             info,
             mqtt,
+            format: Format::Topics,
+            muted: false,
             serial,
+            sequence: 0,
+        }
+    }
+
+    pub fn channel(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::Debug => self.debug,
+            Channel::Info => self.info,
+            Channel::Mqtt => self.mqtt,
+        }
+    }
+
+    pub fn set_channel(&mut self, channel: Channel, enabled: bool) {
+        match channel {
+            Channel::Debug => self.debug = enabled,
+            Channel::Info => self.info = enabled,
+            Channel::Mqtt => self.mqtt = enabled,
         }
     }
 
+    /// Stop sending messages, e.g. because the port is used by another protocol
+    pub fn mute(&mut self) {
+        self.muted = true;
+    }
+
+    /// Send bytes of another protocol unframed. Not affected by muting
+    pub fn write_raw(&mut self, data: &[u8]) {
+        self.serial.write(data);
+    }
+
+    /// Wait until all messages are handed to the serial port
+    pub fn flush(&mut self) {
+        self.serial.flush();
+    }
+
+    /// Number of times the transmit buffer ran full
+    pub fn overflows(&self) -> u16 {
+        self.serial.overflows()
+    }
+
+    /// Load the enabled channels from the EEPROM. The current channels are kept if the stored
+    /// data is invalid
+    pub fn load_channels(&mut self, eeprom: &eeprom::Eeprom) {
+        let mut data = [0_u8; STORE_SIZE];
+        eeprom.read(eeprom::LOG_CHANNELS_ADDRESS, &mut data);
+        if eeprom::checksum(&data[..STORE_SIZE - 1]) == data[STORE_SIZE - 1] {
+            for channel in CHANNELS.iter() {
+                self.set_channel(*channel, data[0] & channel.bit() != 0);
+            }
+        }
+    }
+
+    /// Store the enabled channels in the EEPROM
+    pub fn store_channels(&self, eeprom: &mut eeprom::Eeprom) {
+        let mut data = [0_u8; STORE_SIZE];
+        for channel in CHANNELS.iter() {
+            if self.channel(*channel) {
+                data[0] |= channel.bit();
+            }
+        }
+        data[STORE_SIZE - 1] = eeprom::checksum(&data[..STORE_SIZE - 1]);
+        eeprom.write(eeprom::LOG_CHANNELS_ADDRESS, &data);
+    }
+
+    /// Write the header of a binary message
+    #[cfg(feature = "binary")]
+    fn begin_message(&mut self, id: u8) -> Frame<'_> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut frame = Frame {
+            serial: &mut self.serial,
+            crc: CRCu16::crc16ccitt_false(),
+            muted: self.muted,
+            buffer: binary::Buffer::new(),
+        };
+        frame.push(&[id]);
+        frame.push(&sequence.to_le_bytes());
+        frame
+    }
+
+    /// Write the header of a frame
+    #[cfg(feature = "binary")]
+    fn begin_frame(&mut self, kind: Kind) -> Frame<'_> {
+        let mut frame = self.begin_message(binary::TEXT);
+        frame.push(kind.to_str().as_bytes());
+        frame
+    }
+
+    /// Write the header of a frame
+    #[cfg(not(feature = "binary"))]
+    fn begin_frame(&mut self, kind: Kind) -> Frame<'_> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        if !self.muted {
+            ufmt::uwrite!(&mut self.serial, "#").ok();
+        }
+        let mut frame = Frame {
+            serial: &mut self.serial,
+            crc: CRCu16::crc16ccitt_false(),
+            muted: self.muted,
+        };
+        ufmt::uwrite!(
+            &mut frame,
+            "{};{};{};",
+            PROTOCOL_VERSION,
+            sequence,
+            kind.to_str()
+        )
+        .ok();
+        frame
+    }
+
     pub fn debug_bool(&mut self, var: bool, text: &str) {
         if self.debug {
-            ufmt::uwriteln!(&mut self.serial, "{}: {}", text, var).ok();
+            frame!(self, Kind::Debug, "{}: {}", text, var);
         }
     }
 
     pub fn debug_i16(&mut self, var: i16, text: &str) {
         if self.debug {
-            ufmt::uwriteln!(&mut self.serial, "{}: {}", text, var).ok();
+            frame!(self, Kind::Debug, "{}: {}", text, var);
         }
     }
 
     pub fn debug_str(&mut self, text: &str) {
         if self.debug {
-            ufmt::uwriteln!(&mut self.serial, "{}", text).ok();
+            frame!(self, Kind::Debug, "{}", text);
         }
     }
 
     pub fn debug_option_i16(&mut self, var: Option<i16>, text: &str) {
         if self.debug {
             if let Some(temp) = var {
-                ufmt::uwriteln!(&mut self.serial, "{}: {}", text, temp).ok();
+                frame!(self, Kind::Debug, "{}: {}", text, temp);
             } else {
-                ufmt::uwriteln!(&mut self.serial, "{}: None", text).ok();
+                frame!(self, Kind::Debug, "{}: None", text);
             }
         }
     }
 
     pub fn info_bool(&mut self, var: bool, text: &str) {
         if self.info {
-            ufmt::uwriteln!(&mut self.serial, "{}: {}", text, var).ok();
+            frame!(self, Kind::Info, "{}: {}", text, var);
         }
     }
 
     pub fn info_i16(&mut self, var: i16, text: &str) {
         if self.info {
-            ufmt::uwriteln!(&mut self.serial, "{}: {}", text, var).ok();
+            frame!(self, Kind::Info, "{}: {}", text, var);
         }
     }
 
     pub fn info_str(&mut self, text: &str) {
         if self.info {
-            ufmt::uwriteln!(&mut self.serial, "{}", text).ok();
+            frame!(self, Kind::Info, "{}", text);
         }
     }
 
+    /// Warnings are always send
+    pub fn warn_str(&mut self, text: &str) {
+        frame!(self, Kind::Warn, "{}", text);
+    }
+
+    /// Errors are always send
+    pub fn error_str(&mut self, text: &str) {
+        frame!(self, Kind::Error, "{}", text);
+    }
+
     pub fn mqtt_bool(&mut self, var: bool, text: &str) {
         if self.mqtt {
             if var {
-                ufmt::uwriteln!(&mut self.serial, "--MQTT--{}:=On", text).ok();
+                frame!(self, Kind::Mqtt, "{}:=On", text);
             } else {
-                ufmt::uwriteln!(&mut self.serial, "--MQTT--{}:=Off", text).ok();
+                frame!(self, Kind::Mqtt, "{}:=Off", text);
             }
         }
     }
@@ -87,14 +437,218 @@ impl SerialLogger {
     pub fn mqtt_option_i16(&mut self, var: Option<i16>, text: &str) {
         if self.mqtt {
             if let Some(var) = var {
-                ufmt::uwriteln!(&mut self.serial, "--MQTT--{}:={}", text, var).ok();
+                frame!(self, Kind::Mqtt, "{}:={}", text, var);
             }
         }
     }
 
+    pub fn mqtt_u32(&mut self, var: u32, topic: &str) {
+        if self.mqtt {
+            frame!(self, Kind::Mqtt, "{}:={}", topic, var);
+        }
+    }
+
+    /// Publish a value to a topic composed of a prefix and a name
+    pub fn mqtt_u32_sub(&mut self, var: u32, prefix: &str, name: &str) {
+        if self.mqtt {
+            frame!(self, Kind::Mqtt, "{}/{}:={}", prefix, name, var);
+        }
+    }
+
     pub fn mqtt_str(&mut self, var: &str, topic: &str) {
         if self.mqtt {
-            ufmt::uwriteln!(&mut self.serial, "--MQTT--{}:={}", topic, var).ok();
+            frame!(self, Kind::Mqtt, "{}:={}", topic, var);
         }
     }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// Publish temperatures, IOs and state as one JSON snapshot. Uptime is given in ms
+    pub fn json_snapshot(
+        &mut self,
+        uptime: u32,
+        state: &str,
+        temperatures: &PlantTemperatures,
+        inputs: &Inputs,
+        outputs: &Outputs,
+    ) {
+        if !self.mqtt {
+            return;
+        }
+
+        let mut frame = self.begin_frame(Kind::Json);
+        frame.write_char('{').ok();
+        ufmt::uwrite!(
+            &mut frame,
+            "\"Zeit\":{},\"State\":\"{}\",\"Temperature\":",
+            uptime,
+            state
+        )
+        .ok();
+        frame.temperatures(&[
+            ("Puffer_Oben", temperatures.buffer_top),
+            ("Puffer_Unten", temperatures.buffer_buttom),
+            ("Warmwasser", temperatures.warm_water),
+            ("Kessel", temperatures.boiler),
+        ]);
+        frame.write_str(",\"Inputs\":").ok();
+        frame.bools(&[
+            ("BrennerStart", inputs.get_start_burner()),
+            ("Pumpe_Warmwasser", inputs.get_warm_water_pump()),
+            ("Pumpe_Heizung", inputs.get_heating_pump()),
+        ]);
+        frame.write_str(",\"Outputs\":").ok();
+        frame.bools(&[
+            ("Brenner_Sperre", outputs.get_burner_inhibit()),
+            ("Magnetventil_Puffer", outputs.get_magnet_valve_buffer()),
+            ("Pumpe_Puffer", outputs.get_pump_buffer()),
+        ]);
+        frame.write_char('}').ok();
+        frame.end();
+    }
+
+    /// Publish the Home Assistant discovery config of a value published on `topic`. The topics
+    /// are relative to the base topic `heat_control`. `extra` holds additional config entries
+    /// and has to start with a comma if not empty
+    pub fn announce(&mut self, component: &str, topic: &str, extra: &str) {
+        // The configs do not fit into a binary message
+        if !self.mqtt || cfg!(feature = "binary") {
+            return;
+        }
+
+        let name = topic.rsplit('/').next().unwrap_or(topic);
+
+        let mut frame = self.begin_frame(Kind::Announce);
+        ufmt::uwrite!(&mut frame, "{}/", component).ok();
+        frame.object_id(topic);
+        frame.write_str(":=").ok();
+        frame.write_char('{').ok();
+        ufmt::uwrite!(
+            &mut frame,
+            "\"~\":\"heat_control\",\"name\":\"{}\",\"stat_t\":\"~/{}\",\"uniq_id\":\"heat_control_",
+            name,
+            topic
+        )
+        .ok();
+        frame.object_id(topic);
+        frame.write_str("\",\"avty_t\":\"~/status\",\"dev\":").ok();
+        frame.write_char('{').ok();
+        frame
+            .write_str("\"ids\":\"heat_control\",\"name\":\"Heat Control\"")
+            .ok();
+        frame.write_char('}').ok();
+        frame.write_str(extra).ok();
+        frame.write_char('}').ok();
+        frame.end();
+    }
+
+    /// Publish the temperatures as binary message
+    #[cfg(feature = "binary")]
+    pub fn binary_temperatures(&mut self, temperatures: &PlantTemperatures) {
+        if !self.mqtt {
+            return;
+        }
+
+        let mut frame = self.begin_message(binary::TEMPERATURES);
+        for temperature in [
+            temperatures.buffer_top,
+            temperatures.buffer_buttom,
+            temperatures.warm_water,
+            temperatures.boiler,
+        ]
+        .iter()
+        {
+            let temperature = temperature.unwrap_or(binary::MISSING_TEMPERATURE);
+            frame.push(&temperature.to_le_bytes());
+        }
+        frame.end();
+    }
+
+    /// Publish the inputs and outputs as binary message
+    #[cfg(feature = "binary")]
+    pub fn binary_io(&mut self, inputs: &Inputs, outputs: &Outputs) {
+        if !self.mqtt {
+            return;
+        }
+
+        let bits = |values: &[(bool, u8)]| {
+            values
+                .iter()
+                .filter(|(value, _)| *value)
+                .fold(0, |bits, (_, bit)| bits | bit)
+        };
+        let inputs = bits(&[
+            (inputs.get_start_burner(), binary::INPUT_START_BURNER),
+            (inputs.get_warm_water_pump(), binary::INPUT_WARM_WATER_PUMP),
+            (inputs.get_heating_pump(), binary::INPUT_HEATING_PUMP),
+        ]);
+        let outputs = bits(&[
+            (outputs.get_burner_inhibit(), binary::OUTPUT_BURNER_INHIBIT),
+            (
+                outputs.get_magnet_valve_buffer(),
+                binary::OUTPUT_MAGNET_VALVE_BUFFER,
+            ),
+            (outputs.get_pump_buffer(), binary::OUTPUT_PUMP_BUFFER),
+        ]);
+
+        let mut frame = self.begin_message(binary::IO);
+        frame.push(&[inputs, outputs]);
+        frame.end();
+    }
+
+    /// Publish the state of the state machine as binary message
+    #[cfg(feature = "binary")]
+    pub fn binary_state(&mut self, state: u8) {
+        if !self.mqtt {
+            return;
+        }
+
+        let mut frame = self.begin_message(binary::STATE);
+        frame.push(&[state]);
+        frame.end();
+    }
+
+    /// Reply to a command. Replies are always send
+    pub fn reply_str(&mut self, text: &str) {
+        frame!(self, Kind::Reply, "{}", text);
+    }
+
+    pub fn reply_bool(&mut self, var: bool, text: &str) {
+        frame!(self, Kind::Reply, "{}: {}", text, var);
+    }
+
+    pub fn reply_i16(&mut self, var: i16, text: &str) {
+        frame!(self, Kind::Reply, "{}: {}", text, var);
+    }
+
+    pub fn reply_u32(&mut self, var: u32, text: &str) {
+        frame!(self, Kind::Reply, "{}: {}", text, var);
+    }
+
+    /// Reply the execution time statistics of a task. Times are given in ms
+    pub fn reply_timing(&mut self, name: &str, min: u32, avg: u32, max: u32, overruns: u32) {
+        frame!(
+            self,
+            Kind::Reply,
+            "{}: min {} avg {} max {} ms, {} overruns",
+            name,
+            min,
+            avg,
+            max,
+            overruns
+        );
+    }
+}
+
+#[cfg(feature = "modbus")]
+impl modbus_port::Transmit for SerialLogger {
+    fn write_raw(&mut self, data: &[u8]) {
+        SerialLogger::write_raw(self, data);
+    }
 }
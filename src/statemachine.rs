@@ -1,23 +1,72 @@
+use crate::exercise::EXERCISE_PUMP_TIME;
+use crate::io::{Travel, ValveTravel, PUMP_ACTIVE_TIME, PUMP_PAUSE_TIME};
+use crate::legionella::{CycleResult, LEGIONELLA_TIMEOUT};
+use crate::timer::{Duration, Instant};
+
+// The pump is always switched off before the valve is closed. Every state with an open valve
+// leaves through `PumpStopping`, which holds the valve open until the pump is off, unless the next
+// state keeps the valve open as well. The pump is only started after the valve output was on for
+// the travel time, as the switch protection can delay the opening. If the valve output is not
+// switched on in time, the states waiting for the valve give up through `PumpStopping`.
+// `BufferLoading` runs the pump with the valve closed. The loading circuit from the boiler to the
+// buffer does not pass the valve, the open valve would connect the buffer to the heating instead.
 machine!(
     enum HeatControl {
-        Init { time: u32 },
+        Init {
+            time: Instant,
+        },
         BufferDisabled,
+        ValveOpening {
+            travel: ValveTravel,
+        },
         BufferEnabled,
-        PumpActive { time: u32 },
-        PumpPause { time: u32 },
+        PumpActive {
+            time: Instant,
+        },
+        PumpPause {
+            time: Instant,
+        },
+        BufferLoading,
+        ExerciseValve {
+            travel: ValveTravel,
+        },
+        ExercisePump {
+            time: Instant,
+        },
+        FrostValveOpening {
+            travel: ValveTravel,
+        },
+        FrostProtection,
+        Legionella {
+            time: Instant,
+            hold: Option<Instant>,
+        },
+        PumpStopping,
     }
 );
 
+/// Names of the states, indexed by `HeatControl::to_u8`. Used by the text protocol, the display
+/// and the decoder of the binary telemetry
+pub const STATE_NAMES: [&str; 14] = [
+    "Error",
+    "Init",
+    "Buffer Disabled",
+    "Buffer Enabled",
+    "Pump Active",
+    "Pump Pause",
+    "Buffer Loading",
+    "Exercise Valve",
+    "Exercise Pump",
+    "Frost Protect",
+    "Legionella",
+    "Valve Opening",
+    "Frost Valve",
+    "Pump Stopping",
+];
+
 impl HeatControl {
     pub fn to_string(&self) -> &'static str {
-        match self {
-            HeatControl::Error => "Error",
-            HeatControl::Init(_) => "Init",
-            HeatControl::BufferDisabled(_) => "Buffer Disabled",
-            HeatControl::BufferEnabled(_) => "Buffer Enabled",
-            HeatControl::PumpActive(_) => "Pump Active",
-            HeatControl::PumpPause(_) => "Pump Pause",
-        }
+        STATE_NAMES[self.to_u8() as usize]
     }
 
     pub fn to_u8(&self) -> u8 {
@@ -28,13 +77,27 @@ impl HeatControl {
             HeatControl::BufferEnabled(_) => 3,
             HeatControl::PumpActive(_) => 4,
             HeatControl::PumpPause(_) => 5,
+            HeatControl::BufferLoading(_) => 6,
+            HeatControl::ExerciseValve(_) => 7,
+            HeatControl::ExercisePump(_) => 8,
+            HeatControl::FrostProtection(_) => 9,
+            HeatControl::Legionella(_) => 10,
+            HeatControl::ValveOpening(_) => 11,
+            HeatControl::FrostValveOpening(_) => 12,
+            HeatControl::PumpStopping(_) => 13,
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Tick {
-    pub time: u32,
+    pub time: Instant,
+}
+/// Cycle of a state that waits for the valve. `open` is the physical state of the valve output
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ValveTick {
+    pub time: Instant,
+    pub open: bool,
 }
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Enable {}
@@ -42,22 +105,102 @@ pub struct Enable {}
 pub struct Disable {}
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct ActivatePump {
-    pub time: u32,
+    pub time: Instant,
+}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StartLoading {}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StopLoading {}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StartExercise {}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Frost {}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Thaw {}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StartLegionella {
+    pub time: Instant,
+}
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CheckLegionella {
+    pub time: Instant,
+    /// Warm water temperature
+    pub temperature: Option<i16>,
+    /// Temperature that has to be reached and the time it has to be held
+    pub target: i16,
+    pub hold_time: Duration,
 }
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PumpStopped {}
 
 transitions!(HeatControl,
 [
     (Init, Tick) => [BufferDisabled, Init],
-    (BufferDisabled, Enable) => BufferEnabled,
-    (BufferEnabled, Disable) => BufferDisabled,
+    (BufferDisabled, Enable) => ValveOpening,
+    (ValveOpening, ValveTick) => [ValveOpening, BufferEnabled, PumpStopping],
+    (ValveOpening, Disable) => PumpStopping,
+    (BufferEnabled, Disable) => PumpStopping,
     (BufferEnabled, ActivatePump) => PumpActive,
     (PumpActive, Tick) => [PumpActive, PumpPause],
-    (PumpPause, Tick) => [PumpPause, BufferEnabled]
+    (PumpPause, Tick) => [PumpPause, BufferEnabled],
+    (BufferDisabled, StartLoading) => BufferLoading,
+    (BufferLoading, StopLoading) => BufferDisabled,
+    (BufferDisabled, StartExercise) => ExerciseValve,
+    (ExerciseValve, ValveTick) => [ExerciseValve, ExercisePump, PumpStopping],
+    (ExerciseValve, Disable) => PumpStopping,
+    (ExercisePump, Tick) => [ExercisePump, PumpStopping],
+    (ExercisePump, Disable) => PumpStopping,
+    (Init, Frost) => FrostValveOpening,
+    (BufferDisabled, Frost) => FrostValveOpening,
+    (ValveOpening, Frost) => FrostValveOpening,
+    (BufferEnabled, Frost) => FrostValveOpening,
+    (PumpActive, Frost) => FrostValveOpening,
+    (PumpPause, Frost) => FrostValveOpening,
+    (BufferLoading, Frost) => FrostValveOpening,
+    (ExerciseValve, Frost) => FrostValveOpening,
+    (ExercisePump, Frost) => FrostValveOpening,
+    (Legionella, Frost) => FrostValveOpening,
+    (PumpStopping, Frost) => FrostValveOpening,
+    (FrostValveOpening, ValveTick) => [FrostValveOpening, FrostProtection, PumpStopping],
+    (FrostValveOpening, Thaw) => PumpStopping,
+    (FrostProtection, Thaw) => PumpStopping,
+    (BufferDisabled, StartLegionella) => Legionella,
+    (Legionella, CheckLegionella) => [Legionella, BufferDisabled],
+    (PumpStopping, PumpStopped) => BufferDisabled
 ]);
 
+/// Implement the frost event for all states. Frost protection has priority over every other state
+macro_rules! on_frost {
+    ($($state:ident),*) => {
+        $(
+            impl $state {
+                pub fn on_frost(self, _: Frost) -> FrostValveOpening {
+                    FrostValveOpening {
+                        travel: ValveTravel::default(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+on_frost!(
+    Init,
+    BufferDisabled,
+    ValveOpening,
+    BufferEnabled,
+    PumpActive,
+    PumpPause,
+    BufferLoading,
+    ExerciseValve,
+    ExercisePump,
+    Legionella,
+    PumpStopping
+);
+
 impl Init {
     pub fn on_tick(self, input: Tick) -> HeatControl {
-        if input.time.wrapping_sub(self.time) > 5_000 {
+        if input.time - self.time > Duration::from_secs(5) {
             HeatControl::BufferDisabled(BufferDisabled {})
         } else {
             HeatControl::Init(self)
@@ -66,14 +209,47 @@ impl Init {
 }
 
 impl BufferDisabled {
-    pub fn on_enable(self, _: Enable) -> BufferEnabled {
-        BufferEnabled {}
+    pub fn on_enable(self, _: Enable) -> ValveOpening {
+        ValveOpening {
+            travel: ValveTravel::default(),
+        }
+    }
+
+    pub fn on_start_loading(self, _: StartLoading) -> BufferLoading {
+        BufferLoading {}
+    }
+
+    pub fn on_start_exercise(self, _: StartExercise) -> ExerciseValve {
+        ExerciseValve {
+            travel: ValveTravel::default(),
+        }
+    }
+
+    pub fn on_start_legionella(self, input: StartLegionella) -> Legionella {
+        Legionella {
+            time: input.time,
+            hold: None,
+        }
+    }
+}
+
+impl ValveOpening {
+    pub fn on_valve_tick(mut self, input: ValveTick) -> HeatControl {
+        match self.travel.update(input.open, input.time) {
+            Travel::Moving => HeatControl::ValveOpening(self),
+            Travel::Open => HeatControl::BufferEnabled(BufferEnabled {}),
+            Travel::Timeout => HeatControl::PumpStopping(PumpStopping {}),
+        }
+    }
+
+    pub fn on_disable(self, _: Disable) -> PumpStopping {
+        PumpStopping {}
     }
 }
 
 impl BufferEnabled {
-    pub fn on_disable(self, _: Disable) -> BufferDisabled {
-        BufferDisabled {}
+    pub fn on_disable(self, _: Disable) -> PumpStopping {
+        PumpStopping {}
     }
 
     pub fn on_activate_pump(self, input: ActivatePump) -> PumpActive {
@@ -81,9 +257,15 @@ impl BufferEnabled {
     }
 }
 
+impl BufferLoading {
+    pub fn on_stop_loading(self, _: StopLoading) -> BufferDisabled {
+        BufferDisabled {}
+    }
+}
+
 impl PumpActive {
     pub fn on_tick(self, input: Tick) -> HeatControl {
-        if input.time.wrapping_sub(self.time) > 60_000 {
+        if input.time - self.time > PUMP_ACTIVE_TIME {
             HeatControl::PumpPause(PumpPause { time: input.time })
         } else {
             HeatControl::PumpActive(self)
@@ -93,10 +275,98 @@ impl PumpActive {
 
 impl PumpPause {
     pub fn on_tick(self, input: Tick) -> HeatControl {
-        if input.time.wrapping_sub(self.time) > 60_000 {
+        if input.time - self.time > PUMP_PAUSE_TIME {
             HeatControl::BufferEnabled(BufferEnabled {})
         } else {
             HeatControl::PumpPause(self)
         }
     }
 }
+
+impl ExerciseValve {
+    pub fn on_valve_tick(mut self, input: ValveTick) -> HeatControl {
+        match self.travel.update(input.open, input.time) {
+            Travel::Moving => HeatControl::ExerciseValve(self),
+            Travel::Open => HeatControl::ExercisePump(ExercisePump { time: input.time }),
+            Travel::Timeout => HeatControl::PumpStopping(PumpStopping {}),
+        }
+    }
+
+    pub fn on_disable(self, _: Disable) -> PumpStopping {
+        PumpStopping {}
+    }
+}
+
+impl ExercisePump {
+    pub fn on_tick(self, input: Tick) -> HeatControl {
+        if input.time - self.time > EXERCISE_PUMP_TIME {
+            HeatControl::PumpStopping(PumpStopping {})
+        } else {
+            HeatControl::ExercisePump(self)
+        }
+    }
+
+    pub fn on_disable(self, _: Disable) -> PumpStopping {
+        PumpStopping {}
+    }
+}
+
+impl FrostValveOpening {
+    pub fn on_valve_tick(mut self, input: ValveTick) -> HeatControl {
+        match self.travel.update(input.open, input.time) {
+            Travel::Moving => HeatControl::FrostValveOpening(self),
+            Travel::Open => HeatControl::FrostProtection(FrostProtection {}),
+            Travel::Timeout => HeatControl::PumpStopping(PumpStopping {}),
+        }
+    }
+
+    pub fn on_thaw(self, _: Thaw) -> PumpStopping {
+        PumpStopping {}
+    }
+}
+
+impl FrostProtection {
+    pub fn on_thaw(self, _: Thaw) -> PumpStopping {
+        PumpStopping {}
+    }
+}
+
+impl Legionella {
+    /// Evaluate the cycle. Returns the result if the cycle is finished
+    pub fn evaluate(&self, input: &CheckLegionella) -> Option<CycleResult> {
+        if let Some(hold) = self.next_hold(input) {
+            if input.time - hold >= input.hold_time {
+                return Some(CycleResult::Success);
+            }
+        }
+        if input.time - self.time >= LEGIONELLA_TIMEOUT {
+            return Some(CycleResult::Failed);
+        }
+        None
+    }
+
+    pub fn on_check_legionella(self, input: CheckLegionella) -> HeatControl {
+        if self.evaluate(&input).is_some() {
+            HeatControl::BufferDisabled(BufferDisabled {})
+        } else {
+            HeatControl::Legionella(Legionella {
+                time: self.time,
+                hold: self.next_hold(&input),
+            })
+        }
+    }
+
+    /// Start of the hold time. The hold time restarts if the temperature drops
+    fn next_hold(&self, input: &CheckLegionella) -> Option<Instant> {
+        match input.temperature {
+            Some(temp) if temp >= input.target => Some(self.hold.unwrap_or(input.time)),
+            _ => None,
+        }
+    }
+}
+
+impl PumpStopping {
+    pub fn on_pump_stopped(self, _: PumpStopped) -> BufferDisabled {
+        BufferDisabled {}
+    }
+}
@@ -2,19 +2,28 @@ use embedded_hal::prelude::_embedded_hal_blocking_delay_DelayUs;
 
 use crate::hal;
 use crate::onewire;
+use crate::parameters::Parameters;
 
 const WARM_WATER_SENSOR_ADD: [u8; 8] = [0x28, 0xFF, 0x2C, 0x99, 0x74, 0x16, 0x04, 0xB5];
 const BUFFER_BUTTOM_SENSOR_ADD: [u8; 8] = [0x28, 0xFF, 0x2F, 0x96, 0x74, 0x16, 0x04, 0x61];
 const BUFFER_TOP_SENSOR_ADD: [u8; 8] = [0x28, 0xFF, 0x4B, 0x96, 0x74, 0x16, 0x04, 0x6F];
 const BOILER_SENSOR_ADD: [u8; 8] = [0x28, 0xFF, 0x7B, 0x58, 0x55, 0x16, 0x03, 0x7B];
 
-pub const MIN_BUFFER_TEMPERATURE: i16 = 550; // m°C
-pub const BUFFER_HYSTERESIS: i16 = 50; // mK
-
 const _ALARM_TEMP_LOW: i8 = 5;
 const _ALARM_TEMP_HIGH: i8 = 95;
 const MEASURERESOLUTION: onewire::ds18b20::MeasureResolution =
     onewire::ds18b20::MeasureResolution::Bit09;
+/// Stop searching the bus after this many devices
+const MAX_SCAN_DEVICES: u8 = 16;
+
+/// Names of the sensors in the order of `ScanResult::found`
+pub const SENSOR_NAMES: [&str; 4] = ["Warmwater", "Buffer Top", "Buffer Bottom", "Boiler"];
+const SENSOR_ADDRESSES: [[u8; 8]; 4] = [
+    WARM_WATER_SENSOR_ADD,
+    BUFFER_TOP_SENSOR_ADD,
+    BUFFER_BUTTOM_SENSOR_ADD,
+    BOILER_SENSOR_ADD,
+];
 
 /// Temperatures in the plant
 #[derive(Default, PartialEq)]
@@ -25,6 +34,48 @@ pub struct PlantTemperatures {
     pub boiler: Option<i16>,
 }
 
+impl PlantTemperatures {
+    /// Check if the boiler is warm enough to start loading the buffer
+    pub fn loading_start(&self, parameters: &Parameters) -> bool {
+        match (self.boiler, self.buffer_buttom) {
+            (Some(boiler), Some(buffer)) => {
+                boiler >= parameters.min_boiler_temperature
+                    && boiler >= buffer + parameters.loading_start_difference
+            }
+            _ => false,
+        }
+    }
+
+    /// Check if loading the buffer has to be stopped. Missing sensors always stop the loading
+    pub fn loading_stop(&self, parameters: &Parameters) -> bool {
+        match (self.boiler, self.buffer_buttom) {
+            (Some(boiler), Some(buffer)) => {
+                boiler < parameters.min_boiler_temperature
+                    || boiler < buffer + parameters.loading_stop_difference
+            }
+            _ => true,
+        }
+    }
+
+    /// Readings in the order warm water, buffer top, buffer bottom, boiler
+    pub fn readings(&self) -> [Option<i16>; 4] {
+        [
+            self.warm_water,
+            self.buffer_top,
+            self.buffer_buttom,
+            self.boiler,
+        ]
+    }
+}
+
+/// Result of a search for devices on the one wire bus
+pub struct ScanResult {
+    /// Number of temperature sensors that answered
+    pub devices: u8,
+    /// Configured sensors that answered. Same order as `SENSOR_NAMES`
+    pub found: [bool; 4],
+}
+
 pub struct Sensors {
     bus: onewire::OneWire<hal::port::Pin<hal::port::mode::OpenDrain>>,
     warm_water: Option<onewire::DS18B20>,
@@ -84,6 +135,35 @@ impl Sensors {
 
         Some(temperatures)
     }
+
+    /// Search the bus for temperature sensors
+    pub fn scan(&mut self) -> ScanResult {
+        let mut delay = hal::delay::Delay::<super::Clock>::new();
+        let mut search = onewire::SearchState::new();
+        let mut result = ScanResult {
+            devices: 0,
+            found: [false; 4],
+        };
+
+        while result.devices < MAX_SCAN_DEVICES {
+            let rom = match self.bus.search(&mut search, &mut delay) {
+                Ok(Some(rom)) => rom,
+                _ => break,
+            };
+            // The search returns a dummy address if no device answers
+            if rom[0] != onewire::DS18B20::family_code() {
+                continue;
+            }
+            result.devices += 1;
+            for (found, address) in result.found.iter_mut().zip(SENSOR_ADDRESSES.iter()) {
+                if rom == *address {
+                    *found = true;
+                }
+            }
+        }
+
+        result
+    }
 }
 
 // Create a sensor and set the options
@@ -0,0 +1,82 @@
+//! Points in time and time spans of the timer
+//!
+//! Only the arithmetic, the clock itself is in `timer`.
+
+use core::ops::{Add, Sub};
+
+/// Point in time in ms since startup. The time wraps after about 49.7 days, so instants can only
+/// be compared if they are less than that apart
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Instant(u32);
+
+/// Time span in ms
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Default)]
+pub struct Duration(u32);
+
+#[allow(dead_code)]
+impl Instant {
+    pub const fn from_millis(millis: u32) -> Self {
+        Self(millis)
+    }
+
+    pub fn as_millis(&self) -> u32 {
+        self.0
+    }
+
+    /// Time from an earlier instant to this instant
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration(self.0.wrapping_sub(earlier.0))
+    }
+
+    /// Check if at least the duration elapsed from this instant until `now`
+    pub fn has_elapsed(&self, now: Instant, duration: Duration) -> bool {
+        now.duration_since(*self) >= duration
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.wrapping_add(duration.0))
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(self.0.wrapping_sub(duration.0))
+    }
+}
+
+#[allow(dead_code)]
+impl Duration {
+    pub const fn from_millis(millis: u32) -> Self {
+        Self(millis)
+    }
+
+    pub const fn from_secs(secs: u32) -> Self {
+        Self(secs * 1_000)
+    }
+
+    pub fn as_millis(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        Duration(self.0.saturating_add(other.0))
+    }
+}
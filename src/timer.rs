@@ -1,6 +1,7 @@
 //! Basic implementation of a timer to be able to calculate time in a state
 
 use crate::chip;
+pub use crate::time::{Duration, Instant};
 
 /// Holds the time since the application is running
 /// Is updated in an interrupt. A safe read to this is possibly in an interrupt free function
@@ -26,10 +27,30 @@ impl Timer1 {
         Self {}
     }
 
-    pub fn millis(&self) -> u32 {
-        let mut my_time = 0;
-        unsafe { avr_device::interrupt::free(|_| my_time = TIME) };
-        my_time
+    pub fn now(&self) -> Instant {
+        Instant::from_millis(millis())
+    }
+}
+
+fn millis() -> u32 {
+    let mut my_time = 0;
+    unsafe { avr_device::interrupt::free(|_| my_time = TIME) };
+    my_time
+}
+
+/// Current time for interrupt handlers
+///
+/// # Safety
+///
+/// Only call with interrupts disabled, e.g. in an interrupt handler
+pub unsafe fn now_in_interrupt() -> Instant {
+    Instant::from_millis(TIME)
+}
+
+impl Instant {
+    /// Time since this instant
+    pub fn elapsed(&self) -> Duration {
+        Instant::from_millis(millis()).duration_since(*self)
     }
 }
 
@@ -0,0 +1,179 @@
+//! Interrupt driven transmitter of the serial port
+//!
+//! Written bytes are collected in a ring buffer and send by the data register empty interrupt,
+//! so writing does not wait for the serial port as long as the buffer has space. If the buffer is
+//! full the writer sends the oldest byte itself as soon as the port is ready. This keeps all
+//! bytes and also works while interrupts are disabled, e.g. during setup. Each time the buffer
+//! runs full is counted as one overflow. While the writer keeps it full, the overflow is only
+//! counted again after the buffer drained to half its size.
+
+use atmega_hal as hal;
+use hal::{
+    pac::USART0,
+    port::{
+        mode::{Input, Output},
+        Pin, PD0, PD1,
+    },
+};
+
+use crate::chip;
+
+pub type UsartWrite =
+    hal::usart::UsartWriter<USART0, Pin<Input, PD0>, Pin<Output, PD1>, super::Clock>;
+
+/// Size of the transmit ring buffer. Has to be a power of two. Kept small for the 2 kB RAM, so the
+/// writer waits for the rest of larger bursts like the JSON snapshot of about 310 bytes. This
+/// takes about 200 ms at 9600 baud, well within the deadline of the telemetry
+const TX_BUFFER_SIZE: usize = 128;
+
+/// Bytes to send by the interrupt. Only accessed in interrupt free sections
+static mut TX_BUFFER: RingBuffer = RingBuffer {
+    data: [0; TX_BUFFER_SIZE],
+    head: 0,
+    tail: 0,
+    full: false,
+    overflows: 0,
+};
+
+struct RingBuffer {
+    data: [u8; TX_BUFFER_SIZE],
+    /// Next position to write
+    head: usize,
+    /// Next position to read
+    tail: usize,
+    /// The buffer ran full and was not drained to half its size since
+    full: bool,
+    /// Number of times the buffer ran full
+    overflows: u16,
+}
+
+impl RingBuffer {
+    /// Append a byte. Returns false if the buffer is full
+    fn push(&mut self, byte: u8) -> bool {
+        let next = (self.head + 1) % TX_BUFFER_SIZE;
+        if next == self.tail {
+            if !self.full {
+                self.full = true;
+                self.overflows = self.overflows.wrapping_add(1);
+            }
+            false
+        } else {
+            self.data[self.head] = byte;
+            self.head = next;
+            true
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.head == self.tail {
+            None
+        } else {
+            let byte = self.data[self.tail];
+            self.tail = (self.tail + 1) % TX_BUFFER_SIZE;
+            if self.len() <= TX_BUFFER_SIZE / 2 {
+                self.full = false;
+            }
+            Some(byte)
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    fn len(&self) -> usize {
+        (self.head + TX_BUFFER_SIZE - self.tail) % TX_BUFFER_SIZE
+    }
+}
+
+pub struct Transmitter {
+    /// Owned to guarantee exclusive access to the transmitter. The bytes are send in the
+    /// interrupt
+    _serial: UsartWrite,
+}
+
+impl Transmitter {
+    pub fn new(serial: UsartWrite) -> Self {
+        Self { _serial: serial }
+    }
+
+    /// Queue a byte for sending
+    pub fn write_byte(&mut self, byte: u8) {
+        loop {
+            let queued = avr_device::interrupt::free(|_| unsafe {
+                if TX_BUFFER.push(byte) {
+                    // The hal does not expose the interrupt enable of the transmitter
+                    let usart = &*chip::USART0::ptr();
+                    usart.ucsr0b.modify(|_, w| w.udrie0().set_bit());
+                    return true;
+                }
+                send_ready();
+                false
+            });
+            if queued {
+                return;
+            }
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for byte in data {
+            self.write_byte(*byte);
+        }
+    }
+
+    /// Wait until all queued bytes are handed to the serial port, e.g. before a reset
+    pub fn flush(&mut self) {
+        while !avr_device::interrupt::free(|_| unsafe {
+            send_ready();
+            TX_BUFFER.is_empty()
+        }) {}
+    }
+
+    /// Number of times the ring buffer ran full
+    pub fn overflows(&self) -> u16 {
+        let mut overflows = 0;
+        avr_device::interrupt::free(|_| overflows = unsafe { TX_BUFFER.overflows });
+        overflows
+    }
+}
+
+impl ufmt::uWrite for Transmitter {
+    type Error = void::Void;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Send all queued bytes without the interrupt, e.g. before the panic handler writes
+///
+/// # Safety
+///
+/// Only call with interrupts disabled and the transmitter of the serial port enabled
+pub unsafe fn drain() {
+    while !TX_BUFFER.is_empty() {
+        send_ready();
+    }
+}
+
+/// Send the next byte if the data register is empty. Only call in interrupt free sections
+unsafe fn send_ready() {
+    let usart = &*chip::USART0::ptr();
+    if usart.ucsr0a.read().udre0().bit_is_set() {
+        if let Some(byte) = TX_BUFFER.pop() {
+            usart.udr0.write(|w| w.bits(byte));
+        }
+    }
+}
+
+#[avr_device::interrupt(atmega328p)]
+unsafe fn USART_UDRE() {
+    let usart = &*chip::USART0::ptr();
+    match TX_BUFFER.pop() {
+        Some(byte) => usart.udr0.write(|w| w.bits(byte)),
+        // Nothing left to send. Enabled again by the next write
+        None => usart.ucsr0b.modify(|_, w| w.udrie0().clear_bit()),
+    }
+}